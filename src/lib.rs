@@ -0,0 +1,6667 @@
+//! Download font files and their associated CSS from Google Fonts.
+//!
+//! Use [`download`] with a [`Config`] to download everything the command-line tool would,
+//! getting back a [`DownloadedFont`] for every font with the [`DownloadedFile`]s written for it,
+//! or [`split_css_into_fonts`] to parse a Google Fonts stylesheet into [`FontInfo`]s yourself.
+
+use base64::prelude::{BASE64_STANDARD, Engine};
+use futures::future::BoxFuture;
+use futures::{Stream, StreamExt, TryStreamExt, stream};
+use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha384};
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::fs;
+use std::io::{self, IsTerminal, Write as _};
+use std::num::NonZeroUsize;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::str;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use zip::ZipWriter;
+use zip::write::SimpleFileOptions;
+
+/// A font file format that Google Fonts can serve.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FontFormat {
+    TrueType,
+    /// An OpenType font with PostScript outlines, which Google Fonts doesn't usually serve.
+    OpenType,
+    /// The compressed format only old versions of Internet Explorer support.
+    EmbeddedOpenType,
+    /// An SVG font, which only old versions of Safari support.
+    Svg,
+    Woff,
+    Woff2,
+    Unknown,
+}
+
+impl FontFormat {
+    #[must_use]
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Self {
+        match s.to_lowercase().as_str() {
+            "truetype" => FontFormat::TrueType,
+            "opentype" => FontFormat::OpenType,
+            "embedded-opentype" => FontFormat::EmbeddedOpenType,
+            "svg" => FontFormat::Svg,
+            "woff" => FontFormat::Woff,
+            "woff2" => FontFormat::Woff2,
+            _ => FontFormat::Unknown,
+        }
+    }
+
+    /// The extension of font files in this format, which is only empty for an unknown format,
+    /// but a font file is never saved in an unknown format, since it's detected from the file first.
+    #[must_use]
+    pub fn to_extension(&self) -> String {
+        match self {
+            FontFormat::TrueType => "ttf".to_string(),
+            FontFormat::OpenType => "otf".to_string(),
+            FontFormat::EmbeddedOpenType => "eot".to_string(),
+            FontFormat::Svg => "svg".to_string(),
+            FontFormat::Woff => "woff".to_string(),
+            FontFormat::Woff2 => "woff2".to_string(),
+            FontFormat::Unknown => String::new(),
+        }
+    }
+
+    #[must_use]
+    pub fn to_css_format(&self) -> &'static str {
+        match self {
+            FontFormat::TrueType => "truetype",
+            FontFormat::OpenType => "opentype",
+            FontFormat::EmbeddedOpenType => "embedded-opentype",
+            FontFormat::Svg => "svg",
+            FontFormat::Woff => "woff",
+            FontFormat::Woff2 => "woff2",
+            FontFormat::Unknown => "unknown",
+        }
+    }
+
+    /// How strongly browsers should prefer this format, lowest first, as the best compressed formats come first in a `src`.
+    fn preference(&self) -> u8 {
+        match self {
+            FontFormat::Woff2 => 0,
+            FontFormat::Woff => 1,
+            FontFormat::TrueType => 2,
+            FontFormat::OpenType => 3,
+            FontFormat::EmbeddedOpenType => 4,
+            FontFormat::Svg => 5,
+            FontFormat::Unknown => 6,
+        }
+    }
+
+    /// The MIME type of font files in this format, like `font/woff2`.
+    #[must_use]
+    pub fn to_mime(&self) -> &'static str {
+        match self {
+            FontFormat::TrueType => "font/ttf",
+            FontFormat::OpenType => "font/otf",
+            FontFormat::EmbeddedOpenType => "application/vnd.ms-fontobject",
+            FontFormat::Svg => "image/svg+xml",
+            FontFormat::Woff => "font/woff",
+            FontFormat::Woff2 => "font/woff2",
+            FontFormat::Unknown => "application/octet-stream",
+        }
+    }
+
+    /// Google Fonts picks the font format based on the User-Agent, so pretend to be a browser that supports this format.
+    fn to_user_agent(&self) -> &'static str {
+        match self {
+            FontFormat::TrueType | FontFormat::OpenType => {
+                "Mozilla/5.0 (Unknown; Linux x86_64) AppleWebKit/538.1 (KHTML, like Gecko) Safari/538.1 Daum/4.1"
+            }
+            FontFormat::EmbeddedOpenType => {
+                "Mozilla/4.0 (compatible; MSIE 8.0; Windows NT 6.1; Trident/4.0)"
+            }
+            FontFormat::Svg => {
+                "Mozilla/5.0 (iPhone; U; CPU iPhone OS 4_1 like Mac OS X; en-us) AppleWebKit/532.9 (KHTML, like Gecko) Version/4.0.5 Mobile/8B117 Safari/6531.22.7"
+            }
+            FontFormat::Woff => {
+                "Mozilla/5.0 (Windows NT 6.1; WOW64; rv:27.0) Gecko/20100101 Firefox/27.0"
+            }
+            FontFormat::Woff2 | FontFormat::Unknown => {
+                "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36"
+            }
+        }
+    }
+
+    /// Whether font files in this format are TrueType or OpenType files, which only differ in their outlines,
+    /// so a font file in one can be served as the other.
+    fn is_sfnt(&self) -> bool {
+        matches!(self, FontFormat::TrueType | FontFormat::OpenType)
+    }
+}
+
+/// The host Google Fonts serves font files from, which is always allowed.
+pub const DEFAULT_ALLOWED_HOST: &str = "fonts.gstatic.com";
+
+/// Options for downloading fonts, matching the command-line options.
+#[allow(clippy::struct_excessive_bools)]
+pub struct Config {
+    /// The Google Fonts CSS URLs to download.
+    /// A URL can start with a label, like `Roboto=https://...`, to write its font files to a subdirectory with that name.
+    pub urls: Vec<String>,
+    /// The directory to write font and CSS files to.
+    pub output_dir: PathBuf,
+    /// The directory to write CSS files to, if it's different to the output directory.
+    pub css_output_dir: Option<PathBuf>,
+    /// Whether to overwrite existing CSS files.
+    pub overwrite_css: bool,
+    /// Whether to overwrite existing font files, and their checksum files.
+    pub overwrite_fonts: bool,
+    /// Only overwrite existing files if their contents have changed.
+    pub update: bool,
+    /// Suppress informational output, including verbose output.
+    pub quiet: bool,
+    /// Enable verbose output.
+    pub verbose: bool,
+    /// Colour the output, like green for files that were written and red for errors.
+    pub color: bool,
+    /// Print each event as a line of JSON instead of text.
+    pub json_logs: bool,
+    /// The path to the font files from the CSS files, or an absolute URL they're served from, without a trailing slash.
+    pub fonts_prefix_in_css: String,
+    /// The number of font files to download at the same time.
+    pub jobs: NonZeroUsize,
+    /// The number of CSS URLs to download at the same time.
+    pub url_jobs: NonZeroUsize,
+    /// Carry on with the other CSS URLs when one fails, instead of stopping.
+    pub keep_going: bool,
+    /// The font format to request from Google Fonts.
+    pub format: FontFormat,
+    /// Write a single CSS file with this name instead of one CSS file per font.
+    pub combine_css: Option<String>,
+    /// How the blocks of CSS are grouped into files, unless `combine_css` is set.
+    pub css_grouping: CssGrouping,
+    /// Also write a CSS file with this name that imports the CSS file of every font.
+    pub index_css: Option<String>,
+    /// Write a manifest describing every downloaded font to this path.
+    pub manifest: Option<PathBuf>,
+    /// The format to write the manifest in.
+    pub manifest_format: ManifestFormat,
+    /// Pack the font files and CSS into a zip archive at this path instead of writing them to the output directories.
+    pub zip: Option<PathBuf>,
+    /// The number of times to retry a failed download.
+    pub retries: u32,
+    /// Save the downloaded CSS in this directory, so it can be reused offline.
+    pub cache_dir: Option<PathBuf>,
+    /// Ask the server whether the cached CSS has changed, skipping the font files if it hasn't.
+    pub if_modified_since: bool,
+    /// Read the CSS from the cache instead of downloading it, and only download font files that aren't in the output directory.
+    pub offline: bool,
+    /// How long to wait for each request before giving up on it.
+    pub timeout: Duration,
+    /// The most requests to send per second, for both CSS and font files, or no limit if `None`.
+    pub rate_limit: Option<f64>,
+    /// Stop downloading any response larger than this many bytes, or never if `None`.
+    pub max_bytes: Option<u64>,
+    /// Show which files would be written without writing anything.
+    pub dry_run: bool,
+    /// The pattern used to name font and CSS files.
+    pub filename_template: FilenameTemplate,
+    /// How font files are arranged in the output directory.
+    pub layout: OutputLayout,
+    /// Download every format listed in a font's sources instead of only the first one.
+    pub all_formats: bool,
+    /// Without `all_formats`, download the source in the first of these formats that a font has,
+    /// or its first source in a known format if it has none of them.
+    pub prefer_formats: Vec<FontFormat>,
+    /// Compute a Subresource Integrity hash for every font file.
+    pub sri: bool,
+    /// Write a `.sha256` file next to every font file with the hex SHA-256 digest of the font file.
+    pub checksums: bool,
+    /// Also write a compressed copy of every font and CSS file written in each of these formats.
+    pub precompress: Vec<Precompression>,
+    /// Only download fonts for these writing systems, or every writing system if empty.
+    pub subsets: Vec<String>,
+    /// Only download fonts in these families, ignoring case, or every family if empty.
+    pub families: Vec<String>,
+    /// Upgrade `http://` CSS and font file URLs to `https://`, so the generated CSS never mixes in insecure URLs.
+    pub force_https: bool,
+    /// Only download fonts whose `unicode-range` includes one of these codepoints, or every font if empty.
+    pub codepoints: Vec<RangeInclusive<u32>>,
+    /// Only download fonts with one of these weights, or a variable font covering one of them, or every weight if empty.
+    pub weights: Vec<u16>,
+    /// The hosts font files can be downloaded from, so CSS from somewhere else can't point the downloader anywhere it likes.
+    pub allowed_hosts: Vec<String>,
+    /// Only download the glyphs needed for this text, using a custom subset named after a hash of the text.
+    pub text: Option<String>,
+    /// Add a `.font-<family>` rule with a fallback font stack for every family to the combined, per-family or index CSS.
+    pub fallback_stack: bool,
+    /// Only write the font files, without any CSS.
+    pub no_css: bool,
+    /// Only write the CSS, without downloading the font files.
+    pub css_only: bool,
+    /// Decompress WOFF2 font files and save them as TTF files, with the CSS pointing at the TTF files.
+    pub decompress_to_ttf: bool,
+    /// Write the writing system comment, like `/* latin */`, above each `@font-face` block in the CSS.
+    pub subset_comments: bool,
+    /// Replace the `font-display` descriptor in the CSS with this value, like `swap` or `optional`.
+    pub font_display: Option<String>,
+    /// Start the `src` in the CSS with `local('Family')`, so a copy of the font that's already installed is used instead.
+    pub add_local: bool,
+    /// Write `<link rel="preload">` tags for every font file to this path.
+    pub preload_html: Option<PathBuf>,
+    /// Write a SCSS variable with the path of every font file to this path.
+    pub scss_vars: Option<PathBuf>,
+    /// Write an HTML page showing a sample of every font to this path.
+    pub preview: Option<PathBuf>,
+    /// Send every request through this HTTP, HTTPS or SOCKS5 proxy, instead of the proxy from the environment.
+    pub proxy: Option<String>,
+    /// Send this User-Agent instead of one for a browser that supports the requested format.
+    pub user_agent: Option<String>,
+}
+
+impl Config {
+    /// How the CSS is grouped into files, which is always a single file when it's combined.
+    fn css_grouping(&self) -> CssGrouping {
+        if self.combine_css.is_some() {
+            CssGrouping::Single
+        } else {
+            self.css_grouping
+        }
+    }
+
+    /// The filename of the single CSS file with every font in it, if there is one.
+    fn combined_css_filename(&self) -> Option<&str> {
+        match (&self.combine_css, self.css_grouping) {
+            (Some(css_filename), _) => Some(css_filename),
+            (None, CssGrouping::Single) => Some(DEFAULT_COMBINED_CSS_FILENAME),
+            (None, _) => None,
+        }
+    }
+
+    /// Whether the output directory is `-`, meaning the combined CSS is printed to stdout instead of written to files.
+    #[must_use]
+    pub fn output_is_stdout(&self) -> bool {
+        self.output_dir == Path::new("-")
+    }
+
+    /// The directory CSS files are written to.
+    fn css_dir(&self) -> &PathBuf {
+        self.css_output_dir.as_ref().unwrap_or(&self.output_dir)
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            urls: Vec::new(),
+            output_dir: PathBuf::from("./fonts"),
+            css_output_dir: None,
+            overwrite_css: false,
+            overwrite_fonts: false,
+            update: false,
+            quiet: false,
+            verbose: false,
+            color: false,
+            json_logs: false,
+            fonts_prefix_in_css: ".".to_string(),
+            jobs: NonZeroUsize::new(4).unwrap_or(NonZeroUsize::MIN),
+            url_jobs: NonZeroUsize::new(2).unwrap_or(NonZeroUsize::MIN),
+            keep_going: false,
+            format: FontFormat::Woff2,
+            combine_css: None,
+            css_grouping: CssGrouping::default(),
+            index_css: None,
+            fallback_stack: false,
+            manifest: None,
+            manifest_format: ManifestFormat::default(),
+            zip: None,
+            retries: 3,
+            cache_dir: None,
+            if_modified_since: false,
+            offline: false,
+            timeout: Duration::from_secs(30),
+            rate_limit: None,
+            max_bytes: None,
+            dry_run: false,
+            filename_template: FilenameTemplate::default(),
+            layout: OutputLayout::Flat,
+            all_formats: false,
+            prefer_formats: vec![FontFormat::Woff2, FontFormat::Woff, FontFormat::TrueType],
+            sri: false,
+            checksums: false,
+            precompress: Vec::new(),
+            subsets: Vec::new(),
+            families: Vec::new(),
+            force_https: false,
+            codepoints: Vec::new(),
+            weights: Vec::new(),
+            allowed_hosts: vec![DEFAULT_ALLOWED_HOST.to_string()],
+            text: None,
+            no_css: false,
+            css_only: false,
+            decompress_to_ttf: false,
+            subset_comments: true,
+            font_display: None,
+            add_local: false,
+            preload_html: None,
+            scss_vars: None,
+            preview: None,
+            proxy: None,
+            user_agent: None,
+        }
+    }
+}
+
+/// A single `@font-face` block from a Google Fonts stylesheet.
+#[derive(Debug)]
+pub struct FontInfo {
+    /// The CSS of the block, without the writing system comment.
+    pub css: String,
+    /// The writing system from the comment above the block, like `latin` or `cyrillic-ext`.
+    pub writing_system_name: String,
+    /// The font's family name, like `Roboto`.
+    pub family: String,
+    /// The font's style, like `normal`, `italic` or `oblique 0deg 10deg`.
+    pub style: String,
+    /// The font's weight, like `400`, or a range like `100 900` for a variable font.
+    pub weight: String,
+    /// The font's `font-stretch` value, if it has one.
+    pub stretch: Option<String>,
+    /// The font's `font-display` value, like `swap`, if it has one.
+    pub display: Option<String>,
+    /// The font's `unicode-range` value, if it has one.
+    pub unicode_range: Option<String>,
+    /// The URL and format of every source in the `src` property, skipping `local()` sources.
+    pub sources: Vec<(String, FontFormat)>,
+    /// The URL of the preferred source, which is the first one in a known format.
+    pub url: String,
+    /// The format of the preferred source.
+    pub format: FontFormat,
+    /// The original `src` property, which is replaced when the CSS is rewritten.
+    src: String,
+    /// Added to the filenames to tell apart different font files that would otherwise have the same name.
+    filename_suffix: String,
+}
+
+/// The font files downloaded so far in this run, shared between every CSS URL.
+/// Both sets are keyed by the URL's label too, since a labelled URL writes its own copy of every font file.
+#[derive(Default)]
+struct DownloadedFiles {
+    urls: HashSet<String>,
+    filenames: HashSet<String>,
+    /// The URLs of font files that were skipped because they weren't valid font files.
+    rejected: Vec<String>,
+}
+
+/// Where the font files and CSS are written in this run.
+enum Output {
+    /// Straight to the output directories.
+    Files,
+    /// Into a zip archive in memory, which is written to the zip path in the config at the end.
+    Zip(Box<ZipWriter<io::Cursor<Vec<u8>>>>),
+}
+
+impl Output {
+    fn new(config: &Config) -> Self {
+        if config.zip.is_some() {
+            Output::Zip(Box::new(ZipWriter::new(io::Cursor::new(Vec::new()))))
+        } else {
+            Output::Files
+        }
+    }
+}
+
+/// A font file downloaded from one of the sources of a font.
+#[derive(Debug)]
+pub struct DownloadedFile {
+    pub url: String,
+    pub format: FontFormat,
+    /// The path of the font file relative to the output directory, using `/` between directories.
+    pub filename: String,
+    /// The size of the font file in bytes, or 0 if only the CSS was written.
+    pub size: usize,
+    /// The Subresource Integrity hash of the font file, like `sha384-...`, if it was requested.
+    pub integrity: Option<String>,
+    /// Whether the font file was written, or would have been in a dry run, instead of being skipped.
+    pub status: FileStatus,
+}
+
+/// What happened to an output file in a run.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileStatus {
+    /// The file didn't exist and was written.
+    Written,
+    /// The file already existed and was kept, or only the CSS was written.
+    Skipped,
+    /// The file already existed and was overwritten, because it had changed or overwriting was requested.
+    Updated,
+}
+
+impl FileStatus {
+    /// Whether the file was written, or would have been in a dry run, whether or not it already existed.
+    #[must_use]
+    pub fn is_written(self) -> bool {
+        self != FileStatus::Skipped
+    }
+
+    fn to_str(self) -> &'static str {
+        match self {
+            FileStatus::Written => "written",
+            FileStatus::Skipped => "skipped",
+            FileStatus::Updated => "updated",
+        }
+    }
+}
+
+impl DownloadedFile {
+    /// Where the font file was written, or would have been, using the output directory from the config it was downloaded with.
+    /// With [`Config::zip`], this is the archive's path joined with the file's name inside it, like `fonts.zip/roboto.woff2`.
+    #[must_use]
+    pub fn path(&self, config: &Config) -> PathBuf {
+        let path = config.output_dir.join(&self.filename);
+        match &config.zip {
+            Some(zip_path) => zip_path.join(zip_entry_name(&path, config)),
+            None => path,
+        }
+    }
+}
+
+/// How a font file in the output directory compares to the one Google Fonts serves.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VerifyStatus {
+    /// The font file is the same size as the one Google Fonts serves.
+    Matches,
+    /// The font file is a different size to the one Google Fonts serves.
+    SizeMismatch { local: u64, remote: u64 },
+    /// The font file isn't in the output directory.
+    Missing,
+    /// Google Fonts didn't say how big the font file is.
+    UnknownSize,
+}
+
+/// A font file that was checked against the one Google Fonts serves.
+pub struct VerifiedFile {
+    pub url: String,
+    /// The path of the font file relative to the output directory, using `/` between directories.
+    pub filename: String,
+    pub status: VerifyStatus,
+}
+
+/// A font that was downloaded, along with the font files downloaded for it.
+#[derive(Debug)]
+pub struct DownloadedFont {
+    pub font: FontInfo,
+    pub files: Vec<DownloadedFile>,
+}
+
+impl DownloadedFont {
+    /// The font's CSS pointing at every font file downloaded for it, after its writing system comment if the config keeps them.
+    fn get_new_css(&self, config: &Config) -> String {
+        let files: Vec<(String, FontFormat)> = self
+            .files
+            .iter()
+            .map(|file| (file.filename.clone(), file.format.clone()))
+            .collect();
+        let css = self.font.rewrite_css(
+            &config.fonts_prefix_in_css,
+            &files,
+            config.font_display.as_deref(),
+            config.add_local,
+        );
+        let writing_system_name = &self.font.writing_system_name;
+        if config.subset_comments && !writing_system_name.is_empty() {
+            format!("/* {writing_system_name} */\n{css}")
+        } else {
+            css
+        }
+    }
+}
+
+/// Something that happens during a run, printed as a line of text, or a line of JSON with `--json-logs`.
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Event<'a> {
+    /// Started downloading the CSS from a URL.
+    DownloadingCss { url: &'a str },
+    /// Started reading the cached CSS for a URL.
+    ReadingCachedCss { url: &'a str },
+    /// Started downloading a font file.
+    DownloadingFont { url: &'a str },
+    /// Created an output directory.
+    CreatedDirectory {
+        description: &'a str,
+        path: &'a Path,
+    },
+    /// Wrote a file, or would have without `dry_run`.
+    Wrote {
+        description: &'a str,
+        path: &'a Path,
+        bytes: usize,
+        dry_run: bool,
+    },
+    /// Didn't write a file because of `reason`.
+    Skipped { path: &'a Path, reason: &'a str },
+    /// Something that might be wrong, but doesn't stop the run.
+    Warning { message: String },
+    /// Something that went wrong.
+    Error { message: String },
+    /// Detail about what's happening, only printed in verbose mode.
+    Debug { message: String },
+    /// The totals for the whole run.
+    Summary {
+        #[serde(flatten)]
+        summary: &'a Summary,
+        dry_run: bool,
+    },
+    /// Started waiting for one of some files to change, to run again.
+    Watching { paths: &'a [PathBuf] },
+    /// A watched file changed.
+    Changed { path: &'a Path },
+}
+
+impl Event<'_> {
+    /// Print the event to stderr, so stdout only ever has the data that was asked for, like the CSS from `--print-css`.
+    /// Everything but warnings and errors is left out if the config is quiet, and debug events are only printed if it's verbose.
+    pub fn log(&self, config: &Config) {
+        let is_problem = matches!(self, Event::Warning { .. } | Event::Error { .. });
+        if (config.quiet && !is_problem) || (matches!(self, Event::Debug { .. }) && !config.verbose)
+        {
+            return;
+        }
+        if config.json_logs {
+            if let Ok(json) = serde_json::to_string(self) {
+                eprintln!("{json}");
+            }
+            return;
+        }
+        // ANSI escape codes for green, yellow and red
+        let paint = |text: &str, color: u8| {
+            if config.color {
+                format!("\x1b[{color}m{text}\x1b[0m")
+            } else {
+                text.to_string()
+            }
+        };
+        let line = match self {
+            Event::DownloadingCss { url } => format!("Downloading CSS: '{url}'."),
+            Event::ReadingCachedCss { url } => format!("Reading cached CSS: '{url}'."),
+            Event::DownloadingFont { url } => format!("Downloading font file: '{url}'."),
+            Event::CreatedDirectory { description, path } => {
+                format!("Created {description} '{}'.", path.display())
+            }
+            Event::Wrote {
+                description,
+                path,
+                bytes,
+                dry_run: true,
+            } => format!(
+                "Would write {description} to '{}' ({bytes} bytes).",
+                path.display()
+            ),
+            Event::Wrote {
+                description, path, ..
+            } => format!(
+                "{} {description} to '{}'.",
+                paint("Wrote", 32),
+                path.display()
+            ),
+            Event::Skipped { path, reason } => format!(
+                "{} writing to '{}' ({reason}).",
+                paint("Skipped", 33),
+                path.display()
+            ),
+            Event::Warning { message } => format!("{} {message}", paint("Warning:", 33)),
+            Event::Error { message } => format!("{} {message}", paint("Error:", 31)),
+            Event::Debug { message } => message.clone(),
+            Event::Summary {
+                summary,
+                dry_run: true,
+            } => format!("Dry run: {summary}"),
+            Event::Summary { summary, .. } => summary.to_string(),
+            Event::Watching { paths } => format!(
+                "Watching {} for changes, press Ctrl+C to stop.",
+                paths
+                    .iter()
+                    .map(|path| format!("'{}'", path.display()))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Event::Changed { path } => format!("'{}' changed, running again.", path.display()),
+        };
+        eprintln!("{line}");
+    }
+}
+
+/// Totals for a whole run, printed at the end and included in the manifest.
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct Summary {
+    /// The number of CSS URLs that were downloaded.
+    pub css_urls: usize,
+    /// The number of font files that were written, or would have been in a dry run.
+    pub files_written: usize,
+    /// The number of font files that weren't written, because they already existed or only the CSS was written.
+    pub files_skipped: usize,
+    /// The total size of every font file downloaded.
+    pub bytes_downloaded: u64,
+}
+
+impl Summary {
+    /// Count up the font files downloaded from some CSS URLs.
+    #[must_use]
+    pub fn new(css_urls: usize, fonts: &[DownloadedFont]) -> Self {
+        let mut summary = Summary {
+            css_urls,
+            ..Summary::default()
+        };
+        for file in fonts
+            .iter()
+            .flat_map(|downloaded_font| &downloaded_font.files)
+        {
+            if file.status.is_written() {
+                summary.files_written += 1;
+            } else {
+                summary.files_skipped += 1;
+            }
+            summary.bytes_downloaded += file.size as u64;
+        }
+        summary
+    }
+}
+
+impl std::fmt::Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Processed {} CSS URLs: {} font files written, {} skipped, {} downloaded.",
+            self.css_urls,
+            self.files_written,
+            self.files_skipped,
+            HumanBytes(self.bytes_downloaded)
+        )
+    }
+}
+
+#[derive(Serialize)]
+struct Manifest {
+    summary: Summary,
+    fonts: Vec<ManifestEntry>,
+}
+
+#[derive(Serialize)]
+struct ManifestEntry {
+    family: String,
+    style: String,
+    weight: String,
+    stretch: Option<String>,
+    writing_system: String,
+    url: String,
+    filename: String,
+    format: String,
+    bytes: usize,
+    status: FileStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    integrity: Option<String>,
+}
+
+impl ManifestEntry {
+    fn new(font: &FontInfo, file: &DownloadedFile) -> Self {
+        ManifestEntry {
+            family: font.family.clone(),
+            style: font.style.clone(),
+            weight: font.weight.clone(),
+            stretch: font.stretch.clone(),
+            writing_system: font.writing_system_name.clone(),
+            url: file.url.clone(),
+            filename: file.filename.clone(),
+            format: file.format.to_css_format().to_string(),
+            bytes: file.size,
+            status: file.status,
+            integrity: file.integrity.clone(),
+        }
+    }
+}
+
+/// The version of the Google Fonts CSS API a URL uses.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CssApi {
+    /// The original API, like `https://fonts.googleapis.com/css?family=Roboto:400,700italic`.
+    V1,
+    /// The current API, like `https://fonts.googleapis.com/css2?family=Roboto:ital,wght@0,400;1,700`.
+    V2,
+}
+
+impl CssApi {
+    /// Work out which API a URL uses from its path.
+    #[must_use]
+    pub fn from_url(url: &str) -> Self {
+        let path = url.split(['?', '#']).next().unwrap_or_default();
+        if path.trim_end_matches('/').ends_with("/css2") {
+            CssApi::V2
+        } else {
+            CssApi::V1
+        }
+    }
+}
+
+/// Split a Google Fonts stylesheet into its `@font-face` blocks and parse them, using the comment above each block as its writing system.
+/// A comment names every block after it until the next comment, and blocks before any comment have no writing system.
+/// Each block is parsed separately, so one that's missing a property doesn't stop the others from being used.
+#[must_use]
+pub fn split_css_into_fonts(css: &str) -> Vec<Result<FontInfo, ParseError>> {
+    split_css_blocks(css)
+        .into_iter()
+        .map(|(writing_system_name, block)| FontInfo::parse(block, writing_system_name))
+        .collect()
+}
+
+/// Split a stylesheet into its `@font-face` blocks, each with the writing system from the comment above it.
+fn split_css_blocks(css: &str) -> Vec<(&str, &str)> {
+    let mut blocks = Vec::new();
+    let mut writing_system_name = "";
+    let mut rest = css;
+
+    loop {
+        let comment_start = rest.find("/*");
+        let block_start = rest.find("@font-face");
+        match (comment_start, block_start) {
+            // A comment before the next block names the writing system
+            (Some(comment_start), block_start)
+                if block_start.is_none_or(|block_start| comment_start < block_start) =>
+            {
+                let Some(comment_length) = rest[comment_start..].find("*/") else {
+                    break;
+                };
+                let comment_end = comment_start + comment_length;
+                writing_system_name = rest[comment_start + 2..comment_end].trim();
+                rest = &rest[comment_end + 2..];
+            }
+            (_, Some(block_start)) => {
+                // Find the brace that closes the block, or use the rest of the CSS if it's unclosed
+                let mut depth = 0;
+                let mut block_end = rest.len();
+                for (i, c) in rest[block_start..].char_indices() {
+                    match c {
+                        '{' => depth += 1,
+                        '}' => {
+                            depth -= 1;
+                            if depth == 0 {
+                                block_end = block_start + i + 1;
+                                break;
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                blocks.push((writing_system_name, rest[block_start..block_end].trim()));
+                rest = &rest[block_end..];
+            }
+            _ => break,
+        }
+    }
+
+    blocks
+}
+
+#[derive(Debug)]
+/// A required property was missing from a font's CSS.
+pub struct ParseError {
+    /// The name of the missing property.
+    pub property: &'static str,
+    /// The start of the CSS block the property was missing from.
+    pub snippet: String,
+}
+
+impl ParseError {
+    fn new(property: &'static str, css: &str) -> Self {
+        // Collapse whitespace so the snippet fits on one line
+        let snippet: String = css
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .chars()
+            .take(80)
+            .collect();
+        ParseError { property, snippet }
+    }
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Missing '{}' in CSS block: '{}...'",
+            self.property, self.snippet
+        )
+    }
+}
+
+/// A CSS URL that couldn't be downloaded when carrying on after failures.
+#[derive(Debug)]
+pub struct UrlFailure {
+    pub url: String,
+    pub error: DownloadError,
+}
+
+/// The URLs that failed, like `'https://...', 'https://...'`.
+fn failed_urls(failures: &[UrlFailure]) -> String {
+    quoted_urls(failures.iter().map(|failure| &failure.url))
+}
+
+/// A list of URLs for an error message, like `'https://a.example/css', 'https://b.example/css'`.
+fn quoted_urls<'a>(urls: impl IntoIterator<Item = &'a String>) -> String {
+    urls.into_iter()
+        .map(|url| format!("'{url}'"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// An error that stopped fonts from being listed or downloaded.
+#[derive(Debug, thiserror::Error)]
+pub enum DownloadError {
+    /// A request failed without a response, like a timeout or a refused connection.
+    #[error("Request to '{url}' failed: {source}")]
+    Network {
+        url: String,
+        #[source]
+        source: reqwest::Error,
+    },
+    /// A request got an error status, like 404 for a font that doesn't exist.
+    #[error("Request to '{url}' failed with status {status}.")]
+    BadStatus { url: String, status: u16 },
+    /// A response was bigger than [`Config::max_bytes`].
+    #[error(
+        "Response from '{url}' is too large, it's at least {size} bytes but the limit is {max_bytes} bytes."
+    )]
+    TooLarge {
+        url: String,
+        size: u64,
+        max_bytes: u64,
+    },
+    /// The CSS from a URL didn't have any `@font-face` blocks.
+    #[error(
+        "No @font-face blocks were found in the CSS from '{url}'. Check it's a Google Fonts CSS URL, and that Google Fonts supports the requested format or User-Agent."
+    )]
+    NoFonts { url: String },
+    /// The CSS from a URL had `@font-face` blocks, but none of them could be parsed.
+    #[error("None of the {blocks} @font-face blocks in the CSS from '{url}' could be parsed.")]
+    InvalidFonts { url: String, blocks: usize },
+    /// None of the fonts from a URL are in the requested subsets.
+    #[error("No fonts from '{url}' matched the requested subsets: {reason}")]
+    NoMatchingSubsets { url: String, reason: String },
+    /// Working offline, but the CSS for a URL isn't in the cache.
+    #[error(
+        "No cached CSS for '{url}' in '{}' ({source}), run without --offline to download it first.",
+        path.display()
+    )]
+    NotCached {
+        url: String,
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    /// An output directory's path is already taken by something that isn't a directory, like a file.
+    #[error("Can't use '{}' as the {description}, it already exists but isn't a directory.", path.display())]
+    NotADirectory { path: PathBuf, description: String },
+    /// A font file's URL isn't on one of the allowed hosts.
+    #[error(
+        "Font file '{url}' isn't on an allowed host, use --allow-host {host} to download from '{host}'."
+    )]
+    HostNotAllowed { url: String, host: String },
+    /// A CSS or font file URL uses a scheme other than `http` or `https`.
+    #[error("Can't download '{url}', only http and https URLs are supported, not {scheme}.")]
+    UnsupportedScheme { url: String, scheme: String },
+    /// Some CSS URLs failed when carrying on after failures, but the fonts from the others were downloaded.
+    #[error("{} of the CSS URLs failed: {}.", failures.len(), failed_urls(failures))]
+    UrlsFailed {
+        fonts: Vec<DownloadedFont>,
+        failures: Vec<UrlFailure>,
+    },
+    /// Some downloaded font files weren't valid font files, like an error page, so they were skipped,
+    /// but the rest of the fonts were downloaded.
+    #[error("{} font files were skipped because they aren't valid font files: {}.", urls.len(), quoted_urls(urls))]
+    InvalidFontFiles {
+        fonts: Vec<DownloadedFont>,
+        urls: Vec<String>,
+    },
+    /// Working offline without a cache directory.
+    #[error("A cache directory is needed to work offline.")]
+    NoCacheDir,
+    /// The proxy URL can't be used.
+    #[error("Invalid proxy URL '{url}': {reason}.")]
+    InvalidProxy { url: String, reason: String },
+    /// The HTTP client couldn't be created.
+    #[error("Failed to create HTTP client: {0}")]
+    Client(#[source] reqwest::Error),
+    /// A file or directory couldn't be read or written.
+    #[error("{message}: {source}")]
+    Io {
+        message: String,
+        #[source]
+        source: io::Error,
+    },
+    /// The manifest couldn't be serialized.
+    #[error("Error writing manifest: {0}")]
+    Manifest(#[from] serde_json::Error),
+    /// The manifest couldn't be serialized as TOML.
+    #[error("Error writing manifest: {0}")]
+    ManifestToml(#[from] toml::ser::Error),
+}
+
+impl std::error::Error for ParseError {}
+
+const FILENAME_PLACEHOLDERS: [&str; 7] = [
+    "family", "weight", "style", "stretch", "subset", "ext", "hash",
+];
+
+/// The hash algorithm used for the `{hash}` placeholder in filename templates.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum HashAlgorithm {
+    #[default]
+    Sha256,
+    Sha1,
+    Md5,
+    /// The 64-bit XXH3 hash, which is fast but not cryptographic.
+    Xxhash,
+}
+
+impl HashAlgorithm {
+    /// Parse a hash algorithm name, one of `sha256`, `sha1`, `md5` or `xxhash`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the algorithm name is unknown.
+    pub fn parse(algorithm: &str) -> Result<Self, String> {
+        match algorithm {
+            "sha256" => Ok(HashAlgorithm::Sha256),
+            "sha1" => Ok(HashAlgorithm::Sha1),
+            "md5" => Ok(HashAlgorithm::Md5),
+            "xxhash" | "xxh3" => Ok(HashAlgorithm::Xxhash),
+            _ => Err(format!(
+                "Unknown hash algorithm '{algorithm}', expected one of sha256, sha1, md5 or xxhash."
+            )),
+        }
+    }
+
+    /// The digest of some content.
+    fn digest(self, content: &[u8]) -> Vec<u8> {
+        match self {
+            HashAlgorithm::Sha256 => Sha256::digest(content).to_vec(),
+            HashAlgorithm::Sha1 => sha1::Sha1::digest(content).to_vec(),
+            HashAlgorithm::Md5 => md5::Md5::digest(content).to_vec(),
+            HashAlgorithm::Xxhash => xxhash_rust::xxh3::xxh3_64(content).to_be_bytes().to_vec(),
+        }
+    }
+}
+
+/// How the hash for the `{hash}` placeholder in filename templates is written.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum HashEncoding {
+    #[default]
+    Hex,
+    /// Digits and upper and lower case letters, which fits more of the hash in the same number of characters.
+    Base62,
+}
+
+impl HashEncoding {
+    /// Parse a hash encoding name, either `hex` or `base62`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the encoding name is unknown.
+    pub fn parse(encoding: &str) -> Result<Self, String> {
+        match encoding {
+            "hex" => Ok(HashEncoding::Hex),
+            "base62" => Ok(HashEncoding::Base62),
+            _ => Err(format!(
+                "Unknown hash encoding '{encoding}', expected hex or base62."
+            )),
+        }
+    }
+
+    fn encode(self, digest: &[u8]) -> String {
+        match self {
+            HashEncoding::Hex => to_hex(digest),
+            HashEncoding::Base62 => to_base62(digest),
+        }
+    }
+}
+
+/// A pattern for naming output files, like `{family}-{weight}-{style}-{subset}.{ext}`.
+/// Empty placeholders, like `{stretch}` for fonts without a font-stretch, are left out along with one of their separators.
+#[derive(Clone)]
+pub struct FilenameTemplate {
+    template: String,
+    /// The algorithm used for the `{hash}` placeholder.
+    hash_algorithm: HashAlgorithm,
+    /// How the `{hash}` placeholder writes the hash.
+    hash_encoding: HashEncoding,
+    /// How many characters of the hash the `{hash}` placeholder keeps.
+    hash_length: usize,
+}
+
+impl Default for FilenameTemplate {
+    fn default() -> Self {
+        FilenameTemplate {
+            template: "{family}-{weight}-{style}-{stretch}-{subset}.{ext}".to_string(),
+            hash_algorithm: HashAlgorithm::default(),
+            hash_encoding: HashEncoding::default(),
+            hash_length: 8,
+        }
+    }
+}
+
+impl FilenameTemplate {
+    /// Parse a template, checking that every placeholder is known.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error describing the problem if a placeholder is unknown or unclosed, or if there's no `{ext}` placeholder.
+    pub fn parse(template: &str) -> Result<Self, String> {
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            let Some(end) = rest[start..].find('}') else {
+                return Err(format!(
+                    "Unclosed placeholder in filename template '{template}'."
+                ));
+            };
+            let placeholder = &rest[start + 1..start + end];
+            if !FILENAME_PLACEHOLDERS.contains(&placeholder) {
+                return Err(format!(
+                    "Unknown placeholder '{{{placeholder}}}' in filename template '{template}', expected one of {}.",
+                    FILENAME_PLACEHOLDERS
+                        .map(|name| format!("{{{name}}}"))
+                        .join(", ")
+                ));
+            }
+            rest = &rest[start + end + 1..];
+        }
+        // The CSS files are named using the same template, so they need a different extension to the fonts
+        if !template.contains("{ext}") {
+            return Err(format!(
+                "Filename template '{template}' must contain the '{{ext}}' placeholder."
+            ));
+        }
+        Ok(FilenameTemplate {
+            template: template.to_string(),
+            ..FilenameTemplate::default()
+        })
+    }
+
+    /// Use a different algorithm and number of characters for the `{hash}` placeholder,
+    /// which is the first 8 hex characters of a SHA-256 hash of the font's URL by default.
+    /// The URL is hashed instead of the font file, so the filenames are known before anything is downloaded,
+    /// and Google Fonts gives a changed font file a new URL anyway.
+    /// The whole hash is used if it's shorter than `length`.
+    #[must_use]
+    pub fn with_hash(self, algorithm: HashAlgorithm, length: usize) -> Self {
+        FilenameTemplate {
+            hash_algorithm: algorithm,
+            hash_length: length,
+            ..self
+        }
+    }
+
+    /// Write the hash for the `{hash}` placeholder in base 62 instead of hex.
+    #[must_use]
+    pub fn with_hash_encoding(self, encoding: HashEncoding) -> Self {
+        FilenameTemplate {
+            hash_encoding: encoding,
+            ..self
+        }
+    }
+
+    fn render(&self, font: &FontInfo, extension: &str) -> String {
+        let mut filename = String::new();
+        let mut rest = self.template.as_str();
+        while let Some(start) = rest.find('{') {
+            filename.push_str(&rest[..start]);
+            // Placeholders are validated when parsing the template, so they're always closed
+            let end = start + rest[start..].find('}').unwrap_or(rest.len() - start);
+            let value = match &rest[start + 1..end] {
+                "family" => normalize_family(&font.family),
+                "weight" => normalize_weight(&font.weight),
+                "style" => font.normalized_style(),
+                "stretch" => font
+                    .stretch
+                    .as_deref()
+                    .map(normalize_stretch)
+                    .unwrap_or_default(),
+                "subset" => font.writing_system_name.clone(),
+                "ext" => extension.to_string(),
+                "hash" => {
+                    let digest = self.hash_algorithm.digest(font.url.as_bytes());
+                    let mut hash = self.hash_encoding.encode(&digest);
+                    hash.truncate(self.hash_length);
+                    hash
+                }
+                _ => String::new(),
+            };
+            rest = &rest[(end + 1).min(rest.len())..];
+            if value.is_empty() {
+                // Drop a separator next to an empty placeholder, so optional values don't leave a doubled separator behind
+                if rest.starts_with(['-', '_']) {
+                    rest = &rest[1..];
+                } else if filename.ends_with(['-', '_']) {
+                    filename.pop();
+                }
+            }
+            filename.push_str(&value);
+        }
+        filename.push_str(rest);
+        // Put the suffix before the extension, so files still open with the right program
+        match filename.rsplit_once('.') {
+            Some((stem, extension)) => format!("{stem}{}.{extension}", font.filename_suffix),
+            None => filename + &font.filename_suffix,
+        }
+    }
+}
+
+/// The CSS file every font is combined into if `--css-grouping single` is used without `--combine-css`.
+pub const DEFAULT_COMBINED_CSS_FILENAME: &str = "fonts.css";
+
+/// How the blocks of CSS are grouped into files.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum CssGrouping {
+    /// A CSS file for every `@font-face` block, named like its font file.
+    #[default]
+    PerBlock,
+    /// A CSS file for every family with all of its blocks, like `roboto.css`.
+    PerFamily,
+    /// One CSS file with every block.
+    Single,
+}
+
+impl CssGrouping {
+    /// Parse a CSS grouping name, one of `per-block`, `per-family` or `single`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the grouping name is unknown.
+    pub fn parse(grouping: &str) -> Result<Self, String> {
+        match grouping {
+            "per-block" => Ok(CssGrouping::PerBlock),
+            "per-family" => Ok(CssGrouping::PerFamily),
+            "single" => Ok(CssGrouping::Single),
+            _ => Err(format!(
+                "Unknown CSS grouping '{grouping}', expected one of per-block, per-family or single."
+            )),
+        }
+    }
+}
+
+/// Which format the manifest is written in.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ManifestFormat {
+    /// A JSON object with the summary and every font file.
+    #[default]
+    Json,
+    /// A CSV table with a row for every font file, without the summary.
+    Csv,
+    /// A TOML document with a `[summary]` table and a `[[fonts]]` table for every font file.
+    Toml,
+}
+
+impl ManifestFormat {
+    /// Parse a manifest format name, one of `json`, `csv` or `toml`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the format name is unknown.
+    pub fn parse(format: &str) -> Result<Self, String> {
+        match format {
+            "json" => Ok(ManifestFormat::Json),
+            "csv" => Ok(ManifestFormat::Csv),
+            "toml" => Ok(ManifestFormat::Toml),
+            _ => Err(format!(
+                "Unknown manifest format '{format}', expected one of json, csv or toml."
+            )),
+        }
+    }
+}
+
+/// A format to precompress output files in, so a static file server can serve them without compressing them itself.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Precompression {
+    /// A `.gz` file.
+    Gzip,
+    /// A `.br` file.
+    Brotli,
+}
+
+impl Precompression {
+    /// Parse a compression format name, `gzip` or `br`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the format name is unknown.
+    pub fn parse(format: &str) -> Result<Self, String> {
+        match format {
+            "gzip" | "gz" => Ok(Precompression::Gzip),
+            "br" | "brotli" => Ok(Precompression::Brotli),
+            _ => Err(format!(
+                "Unknown compression format '{format}', expected gzip or br."
+            )),
+        }
+    }
+
+    /// The extension added to the path of a compressed file.
+    #[must_use]
+    pub fn to_extension(self) -> &'static str {
+        match self {
+            Precompression::Gzip => ".gz",
+            Precompression::Brotli => ".br",
+        }
+    }
+
+    /// Compress some bytes as well as the format can, since it only happens once.
+    fn compress(self, contents: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Precompression::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+                io::Write::write_all(&mut encoder, contents)?;
+                encoder.finish()
+            }
+            Precompression::Brotli => {
+                let mut encoder = brotli::CompressorWriter::new(Vec::new(), 4096, 11, 22);
+                io::Write::write_all(&mut encoder, contents)?;
+                Ok(encoder.into_inner())
+            }
+        }
+    }
+}
+
+/// How font files are arranged in the output directory.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum OutputLayout {
+    /// Every font file directly in the output directory.
+    #[default]
+    Flat,
+    /// A directory for each family, like `roboto/`.
+    Family,
+    /// A directory for each subset, like `latin/`.
+    Subset,
+    /// A directory for each family containing a directory for each subset, like `roboto/latin/`.
+    FamilySubset,
+}
+
+impl OutputLayout {
+    /// Parse a layout name, one of `flat`, `family`, `subset` or `family-subset`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the layout name is unknown.
+    pub fn parse(layout: &str) -> Result<Self, String> {
+        match layout {
+            "flat" => Ok(OutputLayout::Flat),
+            "family" => Ok(OutputLayout::Family),
+            "subset" => Ok(OutputLayout::Subset),
+            "family-subset" => Ok(OutputLayout::FamilySubset),
+            _ => Err(format!(
+                "Unknown layout '{layout}', expected one of flat, family, subset or family-subset."
+            )),
+        }
+    }
+}
+
+/// Turn a font family like `Open Sans` into something that can go in a filename.
+/// Spaces and characters that aren't allowed in filenames on some filesystems, like `:` or `/`, become `-`,
+/// without several in a row or any at either end, and a family with nothing left is called `font`.
+fn normalize_family(family: &str) -> String {
+    let family: String = family
+        .to_lowercase()
+        .chars()
+        .map(|c| {
+            if c.is_whitespace() || c.is_control() || r#"<>:"/\|?*"#.contains(c) {
+                '-'
+            } else {
+                c
+            }
+        })
+        .collect();
+    // Windows doesn't allow a filename to end with a dot either
+    let family = family
+        .split('-')
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-");
+    let family = family.trim_matches('.');
+    if family.is_empty() {
+        "font".to_string()
+    } else {
+        family.to_string()
+    }
+}
+
+/// Turn a fonts prefix, which might be a Windows path like `..\\fonts\\`, into the start of a URL like `../fonts`.
+/// Empty and `.` segments are removed, so joining it to a filename with `/` never gives `//` or `/./`,
+/// except that a leading `./` is kept, and so are the slashes after a URL's scheme.
+fn url_prefix(font_prefix: &str) -> String {
+    let font_prefix = font_prefix.replace('\\', "/");
+    let (start, path) = match font_prefix.find("://") {
+        Some(index) => font_prefix.split_at(index + "://".len()),
+        None if font_prefix.starts_with("//") => font_prefix.split_at("//".len()),
+        None => ("", font_prefix.as_str()),
+    };
+    let mut segments: Vec<&str> = path
+        .split('/')
+        .filter(|segment| !segment.is_empty() && *segment != ".")
+        .collect();
+    if start.is_empty() && (path == "." || path.starts_with("./")) {
+        segments.insert(0, ".");
+    }
+    let segments = segments.join("/");
+    if !start.is_empty() {
+        format!("{start}{segments}")
+    } else if path.starts_with('/') && !segments.is_empty() {
+        format!("/{segments}")
+    } else {
+        segments
+    }
+}
+
+/// Turn a font-weight value like `400` or the range `100 900` into something that can go in a filename.
+fn normalize_weight(weight: &str) -> String {
+    weight.split_whitespace().collect::<Vec<_>>().join("-")
+}
+
+/// Turn a font-stretch value like `75%` or `75% 125%` into something that can go in a filename.
+fn normalize_stretch(stretch: &str) -> String {
+    stretch
+        .trim()
+        .to_lowercase()
+        .replace('%', "pct")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// The Subresource Integrity value for some content, which is its base64-encoded SHA-384 digest.
+fn integrity_hash(content: &[u8]) -> String {
+    format!("sha384-{}", BASE64_STANDARD.encode(Sha384::digest(content)))
+}
+
+/// Some bytes, like a digest, in hex.
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{byte:02x}");
+        hex
+    })
+}
+
+/// Some bytes as a big-endian number in base 62, using the digits and then the upper and lower case letters.
+fn to_base62(bytes: &[u8]) -> String {
+    const DIGITS: &[u8; 62] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+    let mut number = bytes.to_vec();
+    let mut base62 = Vec::new();
+    // Long division by 62, which gives the digits from the least significant one
+    while number.iter().any(|&byte| byte != 0) {
+        let mut remainder = 0;
+        for byte in &mut number {
+            let value = remainder * 256 + usize::from(*byte);
+            *byte = u8::try_from(value / 62).unwrap_or_default();
+            remainder = value % 62;
+        }
+        base62.push(char::from(DIGITS[remainder]));
+    }
+    if base62.is_empty() {
+        base62.push('0');
+    }
+    base62.iter().rev().collect()
+}
+
+/// The SHA-256 digest of some content, in hex.
+fn sha256_hex(content: &[u8]) -> String {
+    to_hex(&Sha256::digest(content))
+}
+
+/// The first 8 hex characters of the SHA-256 digest of some text.
+fn short_hash(text: &str) -> String {
+    let mut hash = sha256_hex(text.as_bytes());
+    hash.truncate(8);
+    hash
+}
+
+/// The contents of a checksum file for a font file, in the format `sha256sum` reads and writes.
+fn checksum_file(font_file_bytes: &[u8], font_path: &Path) -> String {
+    let filename = font_path
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    format!("{}  {filename}\n", sha256_hex(font_file_bytes))
+}
+
+/// The value of a property in a CSS block, up to the semicolon after it.
+fn css_property<'a>(css: &'a str, property: &str) -> Option<&'a str> {
+    css.split(&format!("{property}: "))
+        .nth(1)
+        .and_then(|rest| rest.split(';').next())
+}
+
+/// Get the URL and format of every source in a `src` property, skipping `local()` sources.
+fn parse_sources(src: &str) -> Vec<(String, FontFormat)> {
+    split_sources(src)
+        .into_iter()
+        .filter_map(|source| {
+            // Extract the URL from the url() part
+            let url_start = source.find("url(")? + 4;
+            let url_end = source[url_start..].find(')')? + url_start;
+            let url = source[url_start..url_end]
+                .trim()
+                .trim_matches(['"', '\''])
+                .to_string();
+
+            // Extract the format from the format() part
+            let format = source
+                .find("format(")
+                .map_or(FontFormat::Unknown, |format_start| {
+                    let format_start = format_start + 7;
+                    let format_end = source[format_start..]
+                        .find(')')
+                        .map_or(source.len(), |i| format_start + i);
+                    FontFormat::from_str(
+                        source[format_start..format_end]
+                            .trim()
+                            .trim_matches(['"', '\'']),
+                    )
+                });
+            Some((url, format))
+        })
+        .collect()
+}
+
+impl FontInfo {
+    /// Parse an `@font-face` block, with the writing system from the comment above it.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`ParseError`] if the block has no `font-family`, `font-style`, `font-weight`, or `src` with a `url()`.
+    pub fn parse(css: &str, writing_system_name: &str) -> Result<Self, ParseError> {
+        let property = |property: &'static str| {
+            css_property(css, property)
+                .map(str::to_string)
+                .ok_or_else(|| ParseError::new(property, css))
+        };
+        let family = css
+            .split("font-family: '")
+            .nth(1)
+            .and_then(|rest| rest.split("';").next())
+            .map(str::to_string)
+            .ok_or_else(|| ParseError::new("font-family", css))?;
+        let style = property("font-style")?;
+        let weight = property("font-weight")?;
+        let src = property("src")?;
+        let sources = parse_sources(&src);
+        // The preferred source is the first one in a known format
+        let (url, format) = sources
+            .iter()
+            .find(|(_, format)| *format != FontFormat::Unknown)
+            .or_else(|| sources.first())
+            .cloned()
+            .ok_or_else(|| ParseError::new("src url()", css))?;
+        Ok(FontInfo {
+            css: css.to_string(),
+            writing_system_name: writing_system_name.to_string(),
+            family,
+            style,
+            weight,
+            // These are optional, so a missing property isn't an error
+            stretch: property("font-stretch").ok(),
+            // Google Fonts only includes font-display if the URL has a display parameter
+            display: property("font-display").ok(),
+            // Fonts without a unicode-range cover every character
+            unicode_range: property("unicode-range").ok(),
+            sources,
+            url,
+            format,
+            src,
+            filename_suffix: String::new(),
+        })
+    }
+
+    /// The font's style as it's used in filenames, with oblique angles left out, so `oblique 0deg 10deg` becomes `oblique`.
+    #[must_use]
+    pub fn normalized_style(&self) -> String {
+        self.style
+            .split_whitespace()
+            .next()
+            .unwrap_or_default()
+            .to_lowercase()
+    }
+
+    /// Whether this is a variable font covering a range of weights or widths, like `font-weight: 100 900;`.
+    #[must_use]
+    pub fn is_variable(&self) -> bool {
+        let is_range = |value: &str| value.split_whitespace().count() > 1;
+        is_range(&self.weight) || self.stretch.as_deref().is_some_and(is_range)
+    }
+
+    /// The name of the font file for the preferred source.
+    #[must_use]
+    pub fn get_font_filename(&self, template: &FilenameTemplate) -> String {
+        self.get_font_filename_for_format(template, &self.format)
+    }
+
+    /// The name of the font file for a source in a particular format.
+    #[must_use]
+    pub fn get_font_filename_for_format(
+        &self,
+        template: &FilenameTemplate,
+        format: &FontFormat,
+    ) -> String {
+        template.render(self, &format.to_extension())
+    }
+
+    /// The path of the font file for a source in a particular format, relative to the output directory.
+    /// The path always uses `/` between directories, so it can also be used in URLs.
+    #[must_use]
+    pub fn get_font_relative_path(
+        &self,
+        template: &FilenameTemplate,
+        format: &FontFormat,
+        layout: OutputLayout,
+    ) -> String {
+        let mut path = String::new();
+        if matches!(layout, OutputLayout::Family | OutputLayout::FamilySubset) {
+            path.push_str(&normalize_family(&self.family));
+            path.push('/');
+        }
+        if matches!(layout, OutputLayout::Subset | OutputLayout::FamilySubset)
+            && !self.writing_system_name.is_empty()
+        {
+            path.push_str(&self.writing_system_name);
+            path.push('/');
+        }
+        path.push_str(&self.get_font_filename_for_format(template, format));
+        path
+    }
+
+    /// The name of the CSS file for this font.
+    #[must_use]
+    pub fn get_css_filename(&self, template: &FilenameTemplate) -> String {
+        template.render(self, "css")
+    }
+
+    /// The font's CSS with the `src` pointing at the downloaded font files, in order of preference
+    /// with WOFF2 first, after any `local()` sources.
+    /// The `font-display` descriptor is replaced with `font_display` if it's given, or added if the font didn't have one.
+    /// Every other descriptor, including ones this crate doesn't know about, is kept exactly as Google Fonts wrote it.
+    /// Backslashes in `font_prefix`, like in a Windows path, are turned into `/` since it's part of a URL,
+    /// and it's joined to the filenames without any `//` or redundant `./`.
+    #[must_use]
+    pub fn get_new_css(
+        &self,
+        font_prefix: &str,
+        files: &[(String, FontFormat)],
+        font_display: Option<&str>,
+    ) -> String {
+        self.rewrite_css(font_prefix, files, font_display, false)
+    }
+
+    /// Like [`FontInfo::get_new_css`], but also starting the `src` with `local('Family')` if `add_local` is set
+    /// and the font doesn't already have that source.
+    fn rewrite_css(
+        &self,
+        font_prefix: &str,
+        files: &[(String, FontFormat)],
+        font_display: Option<&str>,
+        add_local: bool,
+    ) -> String {
+        let font_prefix = url_prefix(font_prefix);
+        // Keep local() sources first, so a copy of the font that's already installed is still preferred
+        let mut sources: Vec<String> = split_sources(&self.src)
+            .into_iter()
+            .map(str::trim)
+            .filter(|source| source.starts_with("local("))
+            .map(str::to_string)
+            .collect();
+        let local_source = format!("local('{}')", self.family);
+        if add_local && !sources.contains(&local_source) {
+            sources.insert(0, local_source);
+        }
+        let mut files: Vec<&(String, FontFormat)> = files.iter().collect();
+        files.sort_by_key(|(_, format)| format.preference());
+        sources.extend(files.into_iter().map(|(filename, format)| match format {
+            FontFormat::Unknown => format!("url({font_prefix}/{filename})"),
+            _ => format!(
+                "url({font_prefix}/{filename}) format('{}')",
+                format.to_css_format()
+            ),
+        }));
+
+        let css = self.css.replacen(
+            &format!("src: {};", self.src),
+            &format!("src: {};", sources.join(", ")),
+            1,
+        );
+        match (font_display, &self.display) {
+            (None, _) => css,
+            (Some(font_display), Some(display)) => css.replacen(
+                &format!("font-display: {display};"),
+                &format!("font-display: {font_display};"),
+                1,
+            ),
+            // Add the descriptor on its own line before the `src`, with the same indentation
+            (Some(font_display), None) => {
+                let src_start = css.find("src: ").unwrap_or_default();
+                let line_start = css[..src_start].rfind('\n').map_or(0, |i| i + 1);
+                let indentation = &css[line_start..src_start];
+                format!(
+                    "{}font-display: {font_display};\n{indentation}{}",
+                    &css[..src_start],
+                    &css[src_start..]
+                )
+            }
+        }
+    }
+
+    /// Build a canonical `@font-face` block from the parsed fields, instead of rewriting the original CSS.
+    /// The descriptors are always in the same order with the same formatting, whatever the original looked like,
+    /// but descriptors that aren't parsed into a field are left out. Parsing the block gives back the same fields.
+    #[must_use]
+    pub fn to_font_face_css(&self) -> String {
+        let mut sources: Vec<String> = split_sources(&self.src)
+            .into_iter()
+            .map(str::trim)
+            .filter(|source| source.starts_with("local("))
+            .map(str::to_string)
+            .collect();
+        sources.extend(self.sources.iter().map(|(url, format)| match format {
+            FontFormat::Unknown => format!("url({url})"),
+            _ => format!("url({url}) format('{}')", format.to_css_format()),
+        }));
+
+        let mut css = String::from("@font-face {\n");
+        let _ = writeln!(css, "  font-family: '{}';", self.family);
+        let _ = writeln!(css, "  font-style: {};", self.style);
+        let _ = writeln!(css, "  font-weight: {};", self.weight);
+        if let Some(stretch) = &self.stretch {
+            let _ = writeln!(css, "  font-stretch: {stretch};");
+        }
+        if let Some(display) = &self.display {
+            let _ = writeln!(css, "  font-display: {display};");
+        }
+        let _ = writeln!(css, "  src: {};", sources.join(", "));
+        if let Some(unicode_range) = &self.unicode_range {
+            let _ = writeln!(css, "  unicode-range: {unicode_range};");
+        }
+        css.push('}');
+        css
+    }
+}
+
+/// Split the sources in a `src` property on the commas that aren't inside brackets.
+fn split_sources(src: &str) -> Vec<&str> {
+    let mut sources = Vec::new();
+    let mut depth = 0;
+    let mut source_start = 0;
+    for (i, c) in src.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                sources.push(&src[source_start..i]);
+                source_start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    sources.push(&src[source_start..]);
+    sources
+}
+
+/// Create an output directory if it doesn't exist, saying so in verbose mode.
+fn ensure_output_dir(
+    output_dir: &Path,
+    description: &str,
+    config: &Config,
+) -> Result<(), DownloadError> {
+    if output_dir.is_dir() {
+        return Ok(());
+    }
+    // `create_dir_all` only says a file exists, which doesn't explain what's wrong
+    if output_dir.exists() {
+        return Err(DownloadError::NotADirectory {
+            path: output_dir.to_path_buf(),
+            description: description.to_string(),
+        });
+    }
+    fs::create_dir_all(output_dir).map_err(|source| DownloadError::Io {
+        message: format!("Failed to create {description} '{}'", output_dir.display()),
+        source,
+    })?;
+    if config.verbose {
+        Event::CreatedDirectory {
+            description,
+            path: output_dir,
+        }
+        .log(config);
+    }
+    Ok(())
+}
+
+/// Split the label off a URL like `Roboto=https://...`, if it has one.
+/// Only a name that can be used as a single directory counts as a label, so a URL's own `=` isn't mistaken for one.
+fn split_url_label(url: &str) -> (Option<&str>, &str) {
+    match url.split_once('=') {
+        Some((label, url))
+            if !label.is_empty()
+                && label != "."
+                && label != ".."
+                && !label.contains(['/', '\\', ':', '?']) =>
+        {
+            (Some(label), url)
+        }
+        _ => (None, url),
+    }
+}
+
+/// The path of a font file relative to the output directory, inside the label's subdirectory if the URL has one.
+fn font_file_path(
+    font: &FontInfo,
+    format: &FontFormat,
+    label: Option<&str>,
+    config: &Config,
+) -> String {
+    let path = font.get_font_relative_path(&config.filename_template, format, config.layout);
+    match label {
+        Some(label) => format!("{label}/{path}"),
+        None => path,
+    }
+}
+
+/// The User-Agent to send with every request.
+fn user_agent(config: &Config) -> &str {
+    config
+        .user_agent
+        .as_deref()
+        .unwrap_or_else(|| config.format.to_user_agent())
+}
+
+/// Download the CSS from a URL, or read it from the cache when offline.
+/// When online, the CSS is saved to the cache for next time.
+/// Also returns whether the server said the CSS hasn't changed since it was cached, when asking it to.
+async fn fetch_css(
+    client: &Client,
+    url: &str,
+    config: &Config,
+) -> Result<(Vec<u8>, bool), DownloadError> {
+    // Google Fonts serves different CSS to different User-Agents, so they have separate cache entries
+    let cache_path = config.cache_dir.as_ref().map(|cache_dir| {
+        cache_dir.join(format!(
+            "{}.css",
+            short_hash(&format!("{}\n{url}", user_agent(config)))
+        ))
+    });
+    if config.offline {
+        let Some(cache_path) = cache_path else {
+            return Err(DownloadError::NoCacheDir);
+        };
+        let css = fs::read(&cache_path).map_err(|source| DownloadError::NotCached {
+            url: url.to_string(),
+            path: cache_path,
+            source,
+        })?;
+        return Ok((css, false));
+    }
+
+    // The validators are saved next to the cached CSS, and only used if the cached CSS is still there
+    let validators_path = cache_path
+        .as_ref()
+        .map(|cache_path| cache_path.with_extension("json"));
+    let accept = "text/css,*/*;q=0.1";
+    let (css, validators) = match (&cache_path, &validators_path) {
+        (Some(cache_path), Some(validators_path)) if config.if_modified_since => {
+            let cached = fs::read(cache_path).ok().zip(
+                fs::read(validators_path)
+                    .ok()
+                    .and_then(|json| serde_json::from_slice::<Validators>(&json).ok()),
+            );
+            let no_validators = Validators::default();
+            let validators = cached
+                .as_ref()
+                .map_or(&no_validators, |(_, validators)| validators);
+            let user_agent = user_agent(config);
+            let response = with_retries(client, url, config, || {
+                client
+                    .http
+                    .get_if_modified(url, accept, user_agent, config.max_bytes, validators)
+            })
+            .await?;
+            match (response, cached) {
+                (Some((css, validators)), _) => (css, Some(validators)),
+                (None, Some((css, _))) => return Ok((css, true)),
+                // A server shouldn't say nothing's changed without validators, but ask again in case it does
+                (None, None) => (fetch(client, url, accept, config).await?.bytes, None),
+            }
+        }
+        _ => (fetch(client, url, accept, config).await?.bytes, None),
+    };
+    if let Some(cache_path) = cache_path
+        && !config.dry_run
+    {
+        let write_cache = |path: &Path, contents: &[u8]| {
+            path.parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .and_then(|()| write_atomically(path, contents))
+                .map_err(|source| DownloadError::Io {
+                    message: format!("Error writing cached CSS '{}'", path.display()),
+                    source,
+                })
+        };
+        write_cache(&cache_path, &css)?;
+        if let (Some(validators), Some(validators_path)) = (validators, validators_path) {
+            let json = serde_json::to_vec(&validators).expect("validators are always serializable");
+            write_cache(&validators_path, &json)?;
+        }
+    }
+    Ok((css, false))
+}
+
+/// Download the body of a URL, retrying with exponential backoff if the request fails.
+/// The `accept` header says which content types are wanted, and compressed responses are decompressed transparently.
+async fn fetch(
+    client: &Client,
+    url: &str,
+    accept: &str,
+    config: &Config,
+) -> Result<Body, DownloadError> {
+    // Google Fonts serves different CSS content based on the User-Agent.
+    // Without a browser-like User-Agent, it returns a simplified version without writing system comments.
+    // Setting a browser User-Agent ensures we get the full CSS with all writing system information,
+    // and choosing which browser to pretend to be chooses the font format.
+    let user_agent = user_agent(config);
+    with_retries(client, url, config, || {
+        client.http.get(url, accept, user_agent, config.max_bytes)
+    })
+    .await
+}
+
+/// How long to wait before retrying a request, which doubles after every attempt up to a little over a minute.
+fn retry_delay(attempt: u32) -> Duration {
+    Duration::from_secs(1 << attempt.min(6))
+}
+
+/// Send a request, throttled by the rate limit, retrying with exponential backoff if it fails.
+async fn with_retries<'a, T>(
+    client: &Client,
+    url: &str,
+    config: &Config,
+    request: impl Fn() -> BoxFuture<'a, Result<T, HttpError>>,
+) -> Result<T, DownloadError> {
+    let mut attempt = 0;
+    loop {
+        client.throttle().await;
+        match request().await {
+            Ok(body) => return Ok(body),
+            Err(e) if attempt < config.retries && e.is_retryable() => {
+                let delay = retry_delay(attempt);
+                Event::Warning {
+                    message: format!(
+                        "Request to '{url}' failed ({e}), retrying in {} seconds.",
+                        delay.as_secs()
+                    ),
+                }
+                .log(config);
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(e.into_download_error(url)),
+        }
+    }
+}
+
+/// Ask for the size of a URL's body without downloading it, if the server says what it is.
+async fn fetch_size(
+    client: &Client,
+    url: &str,
+    config: &Config,
+) -> Result<Option<u64>, DownloadError> {
+    client.throttle().await;
+    client
+        .http
+        .content_length(url, user_agent(config))
+        .await
+        .map_err(|e| e.into_download_error(url))
+}
+
+/// Sends the requests for the CSS and font files, so tests can answer them without a network.
+trait Http: std::fmt::Debug + Send + Sync {
+    /// Send a GET request and read its body, giving up if it's longer than `max_bytes`.
+    /// The `accept` header says which content types are wanted, and compressed responses are decompressed transparently.
+    fn get<'a>(
+        &'a self,
+        url: &'a str,
+        accept: &'a str,
+        user_agent: &'a str,
+        max_bytes: Option<u64>,
+    ) -> BoxFuture<'a, Result<Body, HttpError>>;
+
+    /// Send a GET request that the server can answer with `304 Not Modified` if the body hasn't changed since it had
+    /// the `validators`, returning `None` if it did and otherwise the body along with its new validators.
+    fn get_if_modified<'a>(
+        &'a self,
+        url: &'a str,
+        accept: &'a str,
+        user_agent: &'a str,
+        max_bytes: Option<u64>,
+        validators: &'a Validators,
+    ) -> BoxFuture<'a, Result<ConditionalBody, HttpError>>;
+
+    /// Send a HEAD request, returning the size of the body if the server says what it is.
+    fn content_length<'a>(
+        &'a self,
+        url: &'a str,
+        user_agent: &'a str,
+    ) -> BoxFuture<'a, Result<Option<u64>, HttpError>>;
+}
+
+/// The body of a response and its `Content-Type` header, if it had one.
+#[derive(Debug)]
+struct Body {
+    bytes: Vec<u8>,
+    content_type: Option<String>,
+}
+
+/// The body of a response to a conditional request and its validators, or `None` if it hasn't changed.
+type ConditionalBody = Option<(Vec<u8>, Validators)>;
+
+/// The `ETag` and `Last-Modified` headers of a response, which are sent back to ask if it's changed.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+struct Validators {
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+/// Read the body of a response a chunk at a time, so a response over the size limit can be stopped early.
+/// A response that says it's over the limit isn't read at all.
+async fn read_body(
+    mut response: reqwest::Response,
+    max_bytes: Option<u64>,
+) -> Result<Vec<u8>, HttpError> {
+    let content_length = response.content_length();
+    if let (Some(size), Some(max_bytes)) = (content_length, max_bytes)
+        && size > max_bytes
+    {
+        return Err(HttpError::TooLarge { size, max_bytes });
+    }
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        body.extend_from_slice(&chunk);
+        if let Some(max_bytes) = max_bytes
+            && body.len() as u64 > max_bytes
+        {
+            return Err(HttpError::TooLarge {
+                size: content_length.unwrap_or(body.len() as u64),
+                max_bytes,
+            });
+        }
+    }
+    Ok(body)
+}
+
+impl Http for reqwest::Client {
+    fn get<'a>(
+        &'a self,
+        url: &'a str,
+        accept: &'a str,
+        user_agent: &'a str,
+        max_bytes: Option<u64>,
+    ) -> BoxFuture<'a, Result<Body, HttpError>> {
+        Box::pin(async move {
+            let response = self
+                .get(url)
+                .header("User-Agent", user_agent)
+                .header(reqwest::header::ACCEPT, accept)
+                .send()
+                .await?
+                .error_for_status()?;
+            let content_type = response
+                .headers()
+                .get(reqwest::header::CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string);
+            Ok(Body {
+                bytes: read_body(response, max_bytes).await?,
+                content_type,
+            })
+        })
+    }
+
+    fn get_if_modified<'a>(
+        &'a self,
+        url: &'a str,
+        accept: &'a str,
+        user_agent: &'a str,
+        max_bytes: Option<u64>,
+        validators: &'a Validators,
+    ) -> BoxFuture<'a, Result<ConditionalBody, HttpError>> {
+        Box::pin(async move {
+            let mut request = self
+                .get(url)
+                .header("User-Agent", user_agent)
+                .header(reqwest::header::ACCEPT, accept);
+            if let Some(etag) = &validators.etag {
+                request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+            }
+            if let Some(last_modified) = &validators.last_modified {
+                request = request.header(reqwest::header::IF_MODIFIED_SINCE, last_modified);
+            }
+            let response = request.send().await?;
+            if response.status() == reqwest::StatusCode::NOT_MODIFIED {
+                return Ok(None);
+            }
+            let response = response.error_for_status()?;
+            let header = |name| {
+                response
+                    .headers()
+                    .get(name)
+                    .and_then(|value| value.to_str().ok())
+                    .map(str::to_string)
+            };
+            let new_validators = Validators {
+                etag: header(reqwest::header::ETAG),
+                last_modified: header(reqwest::header::LAST_MODIFIED),
+            };
+            Ok(Some((
+                read_body(response, max_bytes).await?,
+                new_validators,
+            )))
+        })
+    }
+
+    fn content_length<'a>(
+        &'a self,
+        url: &'a str,
+        user_agent: &'a str,
+    ) -> BoxFuture<'a, Result<Option<u64>, HttpError>> {
+        Box::pin(async move {
+            let response = self
+                .head(url)
+                .header("User-Agent", user_agent)
+                .send()
+                .await?
+                .error_for_status()?;
+            // The body of a response to a HEAD request is always empty, so the length has to come from the header
+            Ok(response
+                .headers()
+                .get(reqwest::header::CONTENT_LENGTH)
+                .and_then(|length| length.to_str().ok()?.parse().ok()))
+        })
+    }
+}
+
+/// Why a request sent through `Http` failed.
+#[derive(Debug)]
+enum HttpError {
+    /// The server responded with an error status.
+    Status(u16),
+    /// The body was longer than `max_bytes`, with the size the server said it was or how much was read before giving up.
+    TooLarge { size: u64, max_bytes: u64 },
+    /// The request failed without a response, like a timeout or a refused connection.
+    Network(reqwest::Error),
+}
+
+impl HttpError {
+    /// Only retry errors that might succeed next time, like rate limiting or server errors, not a missing font.
+    fn is_retryable(&self) -> bool {
+        match self {
+            HttpError::Status(status) => *status == 429 || (500..600).contains(status),
+            HttpError::TooLarge { .. } => false,
+            HttpError::Network(_) => true,
+        }
+    }
+
+    fn into_download_error(self, url: &str) -> DownloadError {
+        let url = url.to_string();
+        match self {
+            HttpError::Status(status) => DownloadError::BadStatus { url, status },
+            HttpError::TooLarge { size, max_bytes } => DownloadError::TooLarge {
+                url,
+                size,
+                max_bytes,
+            },
+            HttpError::Network(source) => DownloadError::Network { url, source },
+        }
+    }
+}
+
+impl From<reqwest::Error> for HttpError {
+    fn from(error: reqwest::Error) -> Self {
+        match error.status() {
+            Some(status) => HttpError::Status(status.as_u16()),
+            None => HttpError::Network(error),
+        }
+    }
+}
+
+impl std::fmt::Display for HttpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HttpError::Status(status) => write!(f, "status {status}"),
+            HttpError::TooLarge { size, max_bytes } => {
+                write!(
+                    f,
+                    "the body is {size} bytes, over the limit of {max_bytes} bytes"
+                )
+            }
+            HttpError::Network(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+/// Warn if Google didn't serve a font in the requested format.
+fn check_font_format(font: &FontInfo, config: &Config) {
+    let (family, format, requested_format) = (&font.family, &font.format, &config.format);
+    let message = if *format == FontFormat::Unknown {
+        format!(
+            "Google Fonts didn't say which format font '{family}' ({}) is in, it will be detected from the downloaded file.",
+            font.writing_system_name
+        )
+    } else if format != requested_format {
+        format!(
+            "Google Fonts served font '{family}' ({}) as {format:?} instead of {requested_format:?}.",
+            font.writing_system_name
+        )
+    } else {
+        return;
+    };
+    Event::Warning { message }.log(config);
+}
+
+/// Guess the format of a font file from the signature at the start of the file.
+/// EOT files have their signature after the sizes and version at the start, and SVG fonts are XML.
+fn detect_font_format(bytes: &[u8]) -> FontFormat {
+    match bytes.get(..4) {
+        Some(b"wOF2") => FontFormat::Woff2,
+        Some(b"wOFF") => FontFormat::Woff,
+        Some(b"\x00\x01\x00\x00" | b"true") => FontFormat::TrueType,
+        Some(b"OTTO") => FontFormat::OpenType,
+        _ if is_eot(bytes) => FontFormat::EmbeddedOpenType,
+        _ if is_svg_font(bytes) => FontFormat::Svg,
+        _ => FontFormat::Unknown,
+    }
+}
+
+/// Whether a file is an EOT file, with one of the three EOT versions and the `LP` magic number after it.
+fn is_eot(bytes: &[u8]) -> bool {
+    matches!(
+        bytes.get(8..12),
+        Some(b"\x00\x00\x01\x00" | b"\x01\x00\x02\x00" | b"\x02\x00\x02\x00")
+    ) && bytes.get(34..36) == Some(b"LP")
+}
+
+/// Whether a file is an SVG font, which is an SVG document with a `<font>` element in it.
+/// Plenty of error pages are XML too, and an SVG image without a font isn't a font file.
+fn is_svg_font(bytes: &[u8]) -> bool {
+    let find = |haystack: &[u8], needle: &[u8]| {
+        haystack
+            .windows(needle.len())
+            .position(|window| window == needle)
+    };
+    let document = bytes.trim_ascii_start();
+    if !document.starts_with(b"<?xml") && !document.starts_with(b"<svg") {
+        return false;
+    }
+    find(document, b"<svg")
+        .is_some_and(|svg_start| find(&document[svg_start..], b"<font").is_some())
+}
+
+/// Log the properties of a font for verbose output.
+fn log_font_info(font: &FontInfo, config: &Config) {
+    let mut message = format!("Font: '{}'.", font.url);
+    let _ = write!(message, "\n  Font family: {}", font.family);
+    let _ = write!(message, "\n  Font style: {}", font.style);
+    let _ = write!(message, "\n  Font weight: {}", font.weight);
+    if font.is_variable() {
+        message.push_str("\n  Variable font: yes");
+    }
+    if let Some(stretch) = &font.stretch {
+        let _ = write!(message, "\n  Font stretch: {stretch}");
+    }
+    if let Some(display) = &font.display {
+        let _ = write!(message, "\n  Font display: {display}");
+    }
+    let _ = write!(message, "\n  Writing system: {}", font.writing_system_name);
+    if let Some(unicode_range) = &font.unicode_range {
+        let _ = write!(message, "\n  Unicode range: {unicode_range}");
+    }
+    Event::Debug { message }.log(config);
+}
+
+/// Whether a `Content-Type` header is one a font file could be served with.
+/// Generic binary types are allowed too, since plenty of servers don't know about font types.
+fn is_font_content_type(content_type: &str) -> bool {
+    let mime_type = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    mime_type.starts_with("font/")
+        || mime_type.starts_with("application/font-")
+        || mime_type.starts_with("application/x-font-")
+        || matches!(
+            mime_type.as_str(),
+            "application/octet-stream"
+                | "application/vnd.ms-fontobject"
+                | "binary/octet-stream"
+                | "image/svg+xml"
+        )
+}
+
+/// The format a font file in a format is saved as, which is only different for WOFF2 files decompressed to TTF.
+fn saved_format(format: &FontFormat, config: &Config) -> FontFormat {
+    match format {
+        FontFormat::Woff2 if config.decompress_to_ttf => FontFormat::TrueType,
+        format => format.clone(),
+    }
+}
+
+/// Check a downloaded font file is in the format it was meant to be, returning the format to save it as.
+/// If the format wasn't known, it's detected from the file instead.
+/// A `Content-Type` that isn't a font is mentioned in the error, or warned about if the file looks fine anyway.
+fn check_downloaded_format(
+    url: &str,
+    format: &FontFormat,
+    font_file: &Body,
+    config: &Config,
+) -> Option<FontFormat> {
+    let unexpected_content_type = font_file
+        .content_type
+        .as_deref()
+        .filter(|content_type| !is_font_content_type(content_type));
+    let detected_format = match detect_font_format(&font_file.bytes) {
+        // Keep the format the CSS says, so the font file has the extension it would've had without downloading it
+        detected_format if detected_format.is_sfnt() && format.is_sfnt() => format.clone(),
+        detected_format => detected_format,
+    };
+    // A font file read back from the output directory when offline has already been decompressed
+    if detected_format == FontFormat::Unknown
+        || (*format != FontFormat::Unknown
+            && detected_format != *format
+            && detected_format != saved_format(format, config))
+    {
+        let content_type_note = unexpected_content_type
+            .map(|content_type| format!(", and the server said it's '{content_type}'"))
+            .unwrap_or_default();
+        Event::Error {
+            message: format!(
+                "Skipping font file '{url}', expected a {format:?} file but the downloaded file looks like {detected_format:?}{content_type_note}."
+            ),
+        }
+        .log(config);
+        return None;
+    }
+    if let Some(content_type) = unexpected_content_type {
+        Event::Warning {
+            message: format!(
+                "The server said font file '{url}' is '{content_type}', but it looks like a {detected_format:?} file."
+            ),
+        }
+        .log(config);
+    }
+    Some(detected_format)
+}
+
+/// Whether a name matches a glob pattern, where `*` matches any characters, including none, and `?` matches one character.
+fn matches_glob(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    // Where the last `*` was in the pattern and what it had matched up to in the name, to backtrack to
+    let mut star = None;
+    let (mut p, mut n) = (0, 0);
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                // Let the last `*` match one more character and try again from there
+                Some((star_p, star_n)) => {
+                    star = Some((star_p, star_n + 1));
+                    p = star_p + 1;
+                    n = star_n + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Keep only the fonts in one of the subsets, or every font if no subsets are given.
+/// A subset can be a glob pattern, like `latin*` for both `latin` and `latin-ext`.
+/// If no fonts match, the error lists the subsets that are available instead.
+fn filter_subsets(fonts: Vec<FontInfo>, subsets: &[String]) -> Result<Vec<FontInfo>, String> {
+    if subsets.is_empty() {
+        return Ok(fonts);
+    }
+    let mut available_subsets: Vec<&str> = Vec::new();
+    for font in &fonts {
+        if !available_subsets.contains(&font.writing_system_name.as_str()) {
+            available_subsets.push(&font.writing_system_name);
+        }
+    }
+    let available_subsets = available_subsets.join(", ");
+    let filtered_fonts: Vec<FontInfo> = fonts
+        .into_iter()
+        .filter(|font| {
+            subsets
+                .iter()
+                .any(|subset| matches_glob(subset, &font.writing_system_name))
+        })
+        .collect();
+    if filtered_fonts.is_empty() {
+        return Err(format!("the available subsets are {available_subsets}."));
+    }
+    Ok(filtered_fonts)
+}
+
+/// Keep only the fonts in one of the families, ignoring case, or every font if no families are given.
+fn filter_families(fonts: Vec<FontInfo>, families: &[String]) -> Vec<FontInfo> {
+    if families.is_empty() {
+        return fonts;
+    }
+    fonts
+        .into_iter()
+        .filter(|font| {
+            families
+                .iter()
+                .any(|requested| requested.to_lowercase() == font.family.to_lowercase())
+        })
+        .collect()
+}
+
+/// The weights a `font-weight` value covers, like `400`, `bold` or `100 900` for a variable font.
+fn weight_range(weight: &str) -> Option<RangeInclusive<u16>> {
+    let parse_weight = |weight: &str| match weight {
+        "normal" => Some(400),
+        "bold" => Some(700),
+        weight => weight.parse().ok(),
+    };
+    let mut weights = weight.split_whitespace().map(parse_weight);
+    match (weights.next(), weights.next(), weights.next()) {
+        (Some(Some(weight)), None, None) => Some(weight..=weight),
+        (Some(Some(start)), Some(Some(end)), None) => Some(start.min(end)..=start.max(end)),
+        _ => None,
+    }
+}
+
+/// Keep only the fonts with one of the weights, or every font if no weights are given, also returning the weights no font has.
+/// Fonts with a weight that can't be parsed are always kept.
+fn filter_weights(fonts: Vec<FontInfo>, weights: &[u16]) -> (Vec<FontInfo>, Vec<u16>) {
+    if weights.is_empty() {
+        return (fonts, Vec::new());
+    }
+    let font_ranges: Vec<Option<RangeInclusive<u16>>> = fonts
+        .iter()
+        .map(|font| weight_range(&font.weight))
+        .collect();
+    let missing_weights = weights
+        .iter()
+        .filter(|weight| {
+            !font_ranges
+                .iter()
+                .any(|range| range.as_ref().is_some_and(|range| range.contains(weight)))
+        })
+        .copied()
+        .collect();
+    let fonts = fonts
+        .into_iter()
+        .zip(font_ranges)
+        .filter_map(|(font, range)| match range {
+            Some(range) if !weights.iter().any(|weight| range.contains(weight)) => None,
+            _ => Some(font),
+        })
+        .collect();
+    (fonts, missing_weights)
+}
+
+/// Parse a `unicode-range` value like `U+0000-00FF, U+0131, U+4??` into the ranges of codepoints it covers.
+///
+/// # Errors
+///
+/// Returns an error if one of the ranges isn't a codepoint, a range of codepoints or a wildcard range.
+pub fn parse_unicode_range(unicode_range: &str) -> Result<Vec<RangeInclusive<u32>>, String> {
+    let parse_codepoint = |hex: &str| {
+        u32::from_str_radix(hex, 16)
+            .ok()
+            .filter(|codepoint| hex.len() <= 6 && *codepoint <= 0x0010_FFFF)
+    };
+    unicode_range
+        .split(',')
+        .map(|range| {
+            let range = range.trim();
+            let invalid =
+                || format!("Invalid unicode range '{range}', expected a range like U+0000-00FF.");
+            let hex = range
+                .strip_prefix("U+")
+                .or_else(|| range.strip_prefix("u+"))
+                .ok_or_else(invalid)?;
+            let (start, end) = match hex.split_once('-') {
+                Some((start, end)) => (parse_codepoint(start), parse_codepoint(end)),
+                // A wildcard range like U+4?? covers every codepoint the question marks could be
+                None if hex.contains('?') => (
+                    parse_codepoint(&hex.replace('?', "0")),
+                    parse_codepoint(&hex.replace('?', "F")),
+                ),
+                None => (parse_codepoint(hex), parse_codepoint(hex)),
+            };
+            match (start, end) {
+                (Some(start), Some(end)) if start <= end => Ok(start..=end),
+                _ => Err(invalid()),
+            }
+        })
+        .collect()
+}
+
+/// Keep only the fonts whose `unicode-range` overlaps one of the codepoint ranges, or every font if no ranges are given.
+/// Fonts without a `unicode-range`, or with one that can't be parsed, cover every codepoint so they're always kept.
+fn filter_codepoints(fonts: Vec<FontInfo>, codepoints: &[RangeInclusive<u32>]) -> Vec<FontInfo> {
+    if codepoints.is_empty() {
+        return fonts;
+    }
+    fonts
+        .into_iter()
+        .filter(|font| {
+            let Some(Ok(font_ranges)) = font.unicode_range.as_deref().map(parse_unicode_range)
+            else {
+                return true;
+            };
+            font_ranges.iter().any(|font_range| {
+                codepoints.iter().any(|range| {
+                    font_range.start() <= range.end() && range.start() <= font_range.end()
+                })
+            })
+        })
+        .collect()
+}
+
+/// Order fonts by family, weight and style, comparing weights as numbers so `900` comes before `1000`.
+/// Subsets aren't compared, so a stable sort keeps them in Google Fonts' order, which puts `latin` last on purpose:
+/// subsets' unicode ranges overlap, and the last face declared for a character is the one that's used.
+fn compare_fonts(a: &FontInfo, b: &FontInfo) -> std::cmp::Ordering {
+    let weight = |font: &FontInfo| {
+        font.weight
+            .split_whitespace()
+            .next()
+            .and_then(|weight| weight.parse::<u32>().ok())
+            .unwrap_or(u32::MAX)
+    };
+    a.family
+        .cmp(&b.family)
+        .then_with(|| weight(a).cmp(&weight(b)))
+        .then_with(|| a.weight.cmp(&b.weight))
+        .then_with(|| a.normalized_style().cmp(&b.normalized_style()))
+}
+
+/// Warn about requested families that none of the fonts are in, which are probably misspelled.
+fn warn_missing_families<'a>(config: &Config, fonts: impl IntoIterator<Item = &'a FontInfo>) {
+    let found_families: Vec<String> = fonts
+        .into_iter()
+        .map(|font| font.family.to_lowercase())
+        .collect();
+    for family in &config.families {
+        if !found_families.contains(&family.to_lowercase()) {
+            Event::Warning {
+                message: format!(
+                    "No fonts in the family '{family}' were found in any of the URLs."
+                ),
+            }
+            .log(config);
+        }
+    }
+}
+
+/// Drop fonts whose file has already been downloaded for the label, and give fonts with different files but the same
+/// path a unique suffix.
+fn deduplicate_fonts(
+    fonts: Vec<FontInfo>,
+    label: Option<&str>,
+    downloaded_files: &mut DownloadedFiles,
+    config: &Config,
+) -> Vec<FontInfo> {
+    fonts
+        .into_iter()
+        .filter_map(|mut font| {
+            let url = font.url.clone();
+            let key = match label {
+                Some(label) => format!("{label}={url}"),
+                None => url.clone(),
+            };
+            if !downloaded_files.urls.insert(key) {
+                Event::Debug {
+                    message: format!("Skipping font file '{url}', it has already been downloaded."),
+                }
+                .log(config);
+                return None;
+            }
+            let mut suffix_number = 1;
+            while !downloaded_files.filenames.insert(font_file_path(
+                &font,
+                &font.format,
+                label,
+                config,
+            )) {
+                suffix_number += 1;
+                font.filename_suffix = format!("-{suffix_number}");
+            }
+            Some(font)
+        })
+        .collect()
+}
+
+/// A progress bar for downloading font files, only shown when printing informational output to a terminal.
+fn font_progress_bar(length: usize, config: &Config) -> Option<ProgressBar> {
+    if config.quiet || config.json_logs || !io::stderr().is_terminal() {
+        return None;
+    }
+    let progress_bar = ProgressBar::new(length as u64).with_style(
+        ProgressStyle::with_template("{bar:40} {pos}/{len} font files ({msg})")
+            .unwrap_or_else(|_| ProgressStyle::default_bar()),
+    );
+    progress_bar.set_message(HumanBytes(0).to_string());
+    Some(progress_bar)
+}
+
+/// Download the font file for every source, except when offline, where font files that are already in the output directory are reused.
+async fn get_font_files(
+    fonts: &[FontInfo],
+    font_sources: &[FontSources],
+    label: Option<&str>,
+    config: &Config,
+    client: &Client,
+) -> Result<Vec<Body>, DownloadError> {
+    let mut existing_files = Vec::new();
+    let mut urls = Vec::new();
+    for (font, sources) in fonts.iter().zip(font_sources) {
+        for (url, format) in sources {
+            let existing_file = if config.offline && !config.overwrite_fonts {
+                fs::read(config.output_dir.join(font_file_path(
+                    font,
+                    &saved_format(format, config),
+                    label,
+                    config,
+                )))
+                .ok()
+                .map(|bytes| Body {
+                    bytes,
+                    content_type: None,
+                })
+            } else {
+                None
+            };
+            if existing_file.is_none() {
+                urls.push(url.clone());
+            }
+            existing_files.push(existing_file);
+        }
+    }
+
+    let mut downloaded_files = download_font_files(&urls, config, client)
+        .await?
+        .into_iter();
+    Ok(existing_files
+        .into_iter()
+        .filter_map(|existing_file| existing_file.or_else(|| downloaded_files.next()))
+        .collect())
+}
+
+async fn download_font_files(
+    urls: &[String],
+    config: &Config,
+    client: &Client,
+) -> Result<Vec<Body>, DownloadError> {
+    let progress_bar = font_progress_bar(urls.len(), config);
+    let total_bytes = AtomicU64::new(0);
+
+    // Buffering keeps the results in the order of the CSS even though the downloads finish in any order,
+    // and stopping at the first error drops the remaining downloads
+    let font_files = stream::iter(urls)
+        .map(|url| {
+            let progress_bar = progress_bar.as_ref();
+            let total_bytes = &total_bytes;
+            async move {
+                // With a progress bar, each file is only mentioned in verbose mode
+                match progress_bar {
+                    Some(progress_bar) if config.verbose => {
+                        progress_bar.println(format!("Downloading font file: '{url}'."));
+                    }
+                    None => Event::DownloadingFont { url }.log(config),
+                    _ => {}
+                }
+                let font_file = fetch(client, url, "*/*", config).await?;
+                if let Some(progress_bar) = progress_bar {
+                    let bytes = total_bytes
+                        .fetch_add(font_file.bytes.len() as u64, Ordering::Relaxed)
+                        + font_file.bytes.len() as u64;
+                    progress_bar.set_message(HumanBytes(bytes).to_string());
+                    progress_bar.inc(1);
+                }
+                Ok(font_file)
+            }
+        })
+        .buffered(config.jobs.get())
+        .try_collect()
+        .await;
+
+    if let Some(progress_bar) = progress_bar {
+        progress_bar.finish_and_clear();
+    }
+    font_files
+}
+
+/// Decode a CSS response, without a byte order mark, replacing invalid UTF-8 instead of failing.
+/// Also returns whether the response was valid UTF-8.
+fn decode_css(bytes: &[u8]) -> (Cow<'_, str>, bool) {
+    let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes);
+    match str::from_utf8(bytes) {
+        Ok(css) => (Cow::Borrowed(css), true),
+        Err(_) => (String::from_utf8_lossy(bytes), false),
+    }
+}
+
+/// Check a CSS or font file URL is `http` or `https`, upgrading `http` to `https` if forced to.
+/// URLs that can't be parsed are left alone to fail when they're downloaded.
+fn check_url_scheme(url: &str, force_https: bool) -> Result<Cow<'_, str>, DownloadError> {
+    let Ok(parsed_url) = reqwest::Url::parse(url) else {
+        return Ok(Cow::Borrowed(url));
+    };
+    match parsed_url.scheme() {
+        "http" if force_https => Ok(Cow::Owned(format!("https{}", &url["http".len()..]))),
+        "http" | "https" => Ok(Cow::Borrowed(url)),
+        scheme => Err(DownloadError::UnsupportedScheme {
+            url: url.to_string(),
+            scheme: scheme.to_string(),
+        }),
+    }
+}
+
+/// The URL to request the CSS from, asking for only the glyphs in the config's text if there is any.
+fn css_request_url<'a>(url: &'a str, config: &Config) -> Cow<'a, str> {
+    match (&config.text, reqwest::Url::parse(url)) {
+        (Some(text), Ok(mut request_url)) => {
+            request_url.query_pairs_mut().append_pair("text", text);
+            Cow::Owned(request_url.to_string())
+        }
+        _ => Cow::Borrowed(url),
+    }
+}
+
+/// Download and parse the CSS from a URL, keeping only the fonts in the requested subsets.
+/// Also returns whether the CSS hasn't changed since the last run, when asking the server about it.
+async fn fetch_fonts(
+    url: &str,
+    config: &Config,
+    client: &Client,
+) -> Result<(Vec<FontInfo>, bool), DownloadError> {
+    let url = &check_url_scheme(url, config.force_https)?;
+    let (response_bytes, not_modified) =
+        fetch_css(client, &css_request_url(url, config), config).await?;
+    // Parse the response
+    let (css_content, is_valid_utf8) = decode_css(&response_bytes);
+    if !is_valid_utf8 {
+        Event::Warning {
+            message: format!(
+                "The CSS from '{url}' isn't valid UTF-8, invalid characters have been replaced."
+            ),
+        }
+        .log(config);
+    }
+    let css_content = css_content.as_ref();
+
+    Event::Debug {
+        message: format!(
+            "Downloaded CSS content ({} bytes)\nGoogle Fonts CSS API: {:?}",
+            css_content.len(),
+            CssApi::from_url(url)
+        ),
+    }
+    .log(config);
+
+    // Both API versions serve @font-face blocks in the same shape, so the same parser handles both
+    let fonts = split_css_into_fonts(css_content);
+
+    Event::Debug {
+        message: format!("Found {} font entries in the CSS", fonts.len()),
+    }
+    .log(config);
+
+    if fonts.is_empty() {
+        return Err(DownloadError::NoFonts {
+            url: url.to_string(),
+        });
+    }
+    let blocks = fonts.len();
+
+    // Skip blocks that are missing required properties instead of aborting the whole download
+    let mut fonts: Vec<FontInfo> = fonts
+        .into_iter()
+        .filter_map(|font| {
+            font.inspect_err(|e| {
+                Event::Warning {
+                    message: format!("Skipping font block: {e}"),
+                }
+                .log(config);
+            })
+            .ok()
+        })
+        .collect();
+    // An error page can look like a stylesheet with one broken block, which is no better than none
+    if fonts.is_empty() {
+        return Err(DownloadError::InvalidFonts {
+            url: url.to_string(),
+            blocks,
+        });
+    }
+    // Google Fonts doesn't name the subset it makes for some text, so name it to keep it apart from the full font
+    if let Some(text) = &config.text {
+        for font in &mut fonts {
+            font.writing_system_name = format!("text-{}", short_hash(text));
+        }
+    }
+    for font in &mut fonts {
+        font.url = check_url_scheme(&font.url, config.force_https)?.into_owned();
+        for (source_url, _) in &mut font.sources {
+            *source_url = check_url_scheme(source_url, config.force_https)?.into_owned();
+        }
+    }
+
+    let fonts = filter_subsets(fonts, &config.subsets).map_err(|reason| {
+        DownloadError::NoMatchingSubsets {
+            url: url.to_string(),
+            reason,
+        }
+    })?;
+    let fonts = filter_families(fonts, &config.families);
+    let fonts = filter_codepoints(fonts, &config.codepoints);
+    let (fonts, missing_weights) = filter_weights(fonts, &config.weights);
+    for weight in missing_weights {
+        Event::Warning {
+            message: format!("No fonts with weight {weight} in the CSS from '{url}'."),
+        }
+        .log(config);
+    }
+    Ok((fonts, not_modified))
+}
+
+/// The URL and format of every source chosen to download for a font.
+type FontSources = Vec<(String, FontFormat)>;
+
+/// Skip fonts that can't be downloaded or were already downloaded, and choose which sources to download for the rest.
+/// Every chosen source has to be on an allowed host.
+fn prepare_fonts(
+    fonts: Vec<FontInfo>,
+    label: Option<&str>,
+    downloaded_files: &mut DownloadedFiles,
+    config: &Config,
+) -> Result<(Vec<FontInfo>, Vec<FontSources>), DownloadError> {
+    for font in &fonts {
+        check_font_format(font, config);
+    }
+    let fonts = deduplicate_fonts(fonts, label, downloaded_files, config);
+
+    let font_sources: Vec<FontSources> = fonts
+        .iter()
+        .map(|font| {
+            if config.all_formats {
+                font.sources
+                    .iter()
+                    .filter(|(_, format)| *format != FontFormat::Unknown)
+                    .cloned()
+                    .collect()
+            } else {
+                vec![preferred_source(font, &config.prefer_formats)]
+            }
+        })
+        .collect();
+    for (url, _) in font_sources.iter().flatten() {
+        check_font_host(url, &config.allowed_hosts)?;
+    }
+    Ok((fonts, font_sources))
+}
+
+/// The source in the first of the preferred formats that the font has, or its preferred source if it has none of them.
+fn preferred_source(font: &FontInfo, prefer_formats: &[FontFormat]) -> (String, FontFormat) {
+    prefer_formats
+        .iter()
+        .find_map(|preferred_format| {
+            font.sources
+                .iter()
+                .find(|(_, format)| format == preferred_format)
+        })
+        .cloned()
+        .unwrap_or_else(|| (font.url.clone(), font.format.clone()))
+}
+
+/// Check a font file's URL is on one of the allowed hosts, ignoring case.
+fn check_font_host(url: &str, allowed_hosts: &[String]) -> Result<(), DownloadError> {
+    let host = reqwest::Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_lowercase))
+        .unwrap_or_default();
+    if allowed_hosts
+        .iter()
+        .any(|allowed_host| allowed_host.to_lowercase() == host)
+    {
+        Ok(())
+    } else {
+        Err(DownloadError::HostNotAllowed {
+            url: url.to_string(),
+            host,
+        })
+    }
+}
+
+fn log_fetching_css(url: &str, config: &Config) {
+    if config.offline {
+        Event::ReadingCachedCss { url }.log(config);
+    } else {
+        Event::DownloadingCss { url }.log(config);
+    }
+}
+
+/// The fonts from the CSS of one of the config's URLs.
+struct FetchedCss<'a> {
+    label: Option<&'a str>,
+    fonts: Vec<FontInfo>,
+    /// Whether the CSS hasn't changed since the last run.
+    not_modified: bool,
+}
+
+/// Download and parse the CSS from every URL in the config, `url_jobs` at a time, along with the URL it came from.
+/// The results are in the same order as the URLs, so fonts that are in more than one are always kept from the same one.
+fn fetch_each_css<'a>(
+    config: &'a Config,
+    client: &'a Client,
+    log_progress: bool,
+) -> impl Stream<Item = (&'a str, Result<FetchedCss<'a>, DownloadError>)> + 'a {
+    stream::iter(&config.urls)
+        .map(move |url| async move {
+            let (label, url) = split_url_label(url);
+            if log_progress {
+                log_fetching_css(url, config);
+            }
+            let fetched = fetch_fonts(url, config, client)
+                .await
+                .map(|(fonts, not_modified)| FetchedCss {
+                    label,
+                    fonts,
+                    not_modified,
+                });
+            (url, fetched)
+        })
+        .buffered(config.url_jobs.get())
+}
+
+/// Download and parse the CSS from every URL in the config, stopping at the first one that fails.
+async fn fetch_all_fonts<'a>(
+    config: &'a Config,
+    client: &'a Client,
+    log_progress: bool,
+) -> Result<Vec<FetchedCss<'a>>, DownloadError> {
+    fetch_each_css(config, client, log_progress)
+        .map(|(_, fetched)| fetched)
+        .try_collect()
+        .await
+}
+
+/// Download and write the font files and CSS for the fonts from one URL's CSS.
+async fn download_parsed_fonts(
+    fonts: Vec<FontInfo>,
+    not_modified: bool,
+    label: Option<&str>,
+    config: &Config,
+    client: &Client,
+    downloaded_files: &mut DownloadedFiles,
+    output: &mut Output,
+) -> Result<Vec<DownloadedFont>, DownloadError> {
+    let (fonts, font_sources) = prepare_fonts(fonts, label, downloaded_files, config)?;
+
+    // If the CSS hasn't changed and every font file from last time is still there, there's nothing to download,
+    // unless they're going in a zip archive, which needs their contents
+    let mut unchanged_files = if not_modified && !config.css_only && config.zip.is_none() {
+        existing_font_files(&fonts, &font_sources, label, config).map(Vec::into_iter)
+    } else {
+        None
+    };
+
+    // Download every font file before writing anything, so a failed download doesn't leave a partial set of files
+    let font_files = if config.css_only || unchanged_files.is_some() {
+        Vec::new()
+    } else {
+        get_font_files(&fonts, &font_sources, label, config, client).await?
+    };
+    let mut font_files = font_files.into_iter();
+
+    // Write each font in the order it appears in the CSS
+    let mut downloaded_fonts = Vec::new();
+    for (font, sources) in fonts.into_iter().zip(font_sources) {
+        log_font_info(&font, config);
+
+        let files = if let Some(unchanged_files) = &mut unchanged_files {
+            let files = unchanged_files.next().unwrap_or_default();
+            for file in &files {
+                Event::Skipped {
+                    path: &file.path(config),
+                    reason: "the CSS hasn't changed since the last run",
+                }
+                .log(config);
+            }
+            files
+        } else if config.css_only {
+            // Point the CSS at where the font files would be, without downloading them
+            planned_font_files(&font, sources, label, config)
+        } else {
+            write_font_files(
+                &font,
+                sources,
+                &mut font_files,
+                label,
+                config,
+                output,
+                &mut downloaded_files.rejected,
+            )?
+        };
+
+        // Don't write CSS pointing at font files that weren't written
+        if files.is_empty() {
+            continue;
+        }
+
+        // When grouping, the CSS is written all at once after every font is downloaded
+        let downloaded_font = DownloadedFont { font, files };
+        if config.css_grouping() == CssGrouping::PerBlock && !config.no_css {
+            write_font_css(&downloaded_font, config, output)?;
+        }
+
+        downloaded_fonts.push(downloaded_font);
+    }
+
+    Ok(downloaded_fonts)
+}
+
+/// The font files that would be written for a font, without downloading them.
+fn planned_font_files(
+    font: &FontInfo,
+    sources: FontSources,
+    label: Option<&str>,
+    config: &Config,
+) -> Vec<DownloadedFile> {
+    sources
+        .into_iter()
+        .map(|(url, format)| {
+            let format = saved_format(&format, config);
+            DownloadedFile {
+                filename: font_file_path(font, &format, label, config),
+                url,
+                format,
+                size: 0,
+                integrity: None,
+                status: FileStatus::Skipped,
+            }
+        })
+        .collect()
+}
+
+/// The font files written for every font by an earlier run, or `None` if any of them are missing.
+fn existing_font_files(
+    fonts: &[FontInfo],
+    font_sources: &[FontSources],
+    label: Option<&str>,
+    config: &Config,
+) -> Option<Vec<Vec<DownloadedFile>>> {
+    fonts
+        .iter()
+        .zip(font_sources)
+        .map(|(font, sources)| {
+            sources
+                .iter()
+                .map(|(url, format)| {
+                    let format = saved_format(format, config);
+                    let filename = font_file_path(font, &format, label, config);
+                    let font_file_bytes = fs::read(config.output_dir.join(&filename)).ok()?;
+                    Some(DownloadedFile {
+                        url: url.clone(),
+                        format,
+                        filename,
+                        size: font_file_bytes.len(),
+                        integrity: config.sri.then(|| integrity_hash(&font_file_bytes)),
+                        status: FileStatus::Skipped,
+                    })
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Check and write the downloaded font files for one font, returning the ones that were written.
+/// The URLs of font files that aren't valid are added to `rejected`.
+fn write_font_files(
+    font: &FontInfo,
+    sources: Vec<(String, FontFormat)>,
+    font_files: &mut impl Iterator<Item = Body>,
+    label: Option<&str>,
+    config: &Config,
+    output: &mut Output,
+    rejected: &mut Vec<String>,
+) -> Result<Vec<DownloadedFile>, DownloadError> {
+    let mut files = Vec::new();
+    for ((url, format), font_file) in sources.into_iter().zip(font_files) {
+        // Check the file really is a font, so an error page isn't saved with a font extension
+        let Some(mut format) = check_downloaded_format(&url, &format, &font_file, config) else {
+            rejected.push(url);
+            continue;
+        };
+        let mut font_file_bytes = font_file.bytes;
+        if saved_format(&format, config) != format {
+            match woff2_patched::convert_woff2_to_ttf(&mut font_file_bytes.as_slice()) {
+                Ok(ttf_bytes) => {
+                    font_file_bytes = ttf_bytes;
+                    format = FontFormat::TrueType;
+                }
+                Err(e) => {
+                    Event::Error {
+                        message: format!(
+                            "Skipping font file '{url}', it couldn't be decompressed to TTF: {e}."
+                        ),
+                    }
+                    .log(config);
+                    rejected.push(url);
+                    continue;
+                }
+            }
+        }
+
+        Event::Debug {
+            message: format!(
+                "  Format: {format:?}\n  Extension: {}\n  Downloaded font file ({} bytes)",
+                format.to_extension(),
+                font_file_bytes.len()
+            ),
+        }
+        .log(config);
+
+        // Write font file
+        let filename = font_file_path(font, &format, label, config);
+        let font_output_path = config.output_dir.join(&filename);
+        // The hash is needed for the manifest and for checking whether the file has changed, so only compute it once
+        let integrity = (config.sri || config.update).then(|| integrity_hash(&font_file_bytes));
+        let status = write_output_file(
+            &font_output_path,
+            &font_file_bytes,
+            integrity.as_deref(),
+            "font file",
+            config.overwrite_fonts,
+            config,
+            output,
+        )?;
+        // Only write a checksum for a font file that was written, so it always matches the file on disk
+        if config.checksums && status.is_written() {
+            let mut checksum_path = font_output_path.clone().into_os_string();
+            checksum_path.push(".sha256");
+            write_output_file(
+                Path::new(&checksum_path),
+                checksum_file(&font_file_bytes, &font_output_path).as_bytes(),
+                None,
+                "checksum file",
+                config.overwrite_fonts,
+                config,
+                output,
+            )?;
+        }
+        // WOFF and WOFF2 files are already compressed, so compressing them again only wastes space
+        if !matches!(format, FontFormat::Woff | FontFormat::Woff2) {
+            write_precompressed(
+                &font_output_path,
+                &font_file_bytes,
+                "font file",
+                config.overwrite_fonts,
+                config,
+                output,
+            )?;
+        }
+
+        files.push(DownloadedFile {
+            url,
+            format,
+            filename,
+            size: font_file_bytes.len(),
+            integrity: integrity.filter(|_| config.sri),
+            status,
+        });
+    }
+    Ok(files)
+}
+
+/// Write a generated file, unless it already exists and `overwrite` isn't set, or this is a dry run.
+/// Returns whether the file was written, skipped or updated, where a dry run counts what would have happened.
+///
+/// When updating, an existing file is only overwritten if its contents have changed.
+/// The `integrity` hash of the contents is used for the comparison if it's already been computed.
+///
+/// When the output is a zip archive the file is added to it instead, and nothing on disk is checked or written.
+fn write_output_file(
+    path: &Path,
+    contents: &[u8],
+    integrity: Option<&str>,
+    description: &str,
+    overwrite: bool,
+    config: &Config,
+    output: &mut Output,
+) -> Result<FileStatus, DownloadError> {
+    if let Output::Zip(zip) = output
+        && let Some(zip_path) = &config.zip
+    {
+        let entry_name = zip_entry_name(path, config);
+        let entry_path = zip_path.join(&entry_name);
+        if !config.dry_run
+            && let Err(source) = zip
+                .start_file(entry_name, SimpleFileOptions::default())
+                .map_err(io::Error::from)
+                .and_then(|()| zip.write_all(contents))
+        {
+            return Err(DownloadError::Io {
+                message: format!("Error adding {description} '{}'", entry_path.display()),
+                source,
+            });
+        }
+        Event::Wrote {
+            description,
+            path: &entry_path,
+            bytes: contents.len(),
+            dry_run: config.dry_run,
+        }
+        .log(config);
+        return Ok(FileStatus::Written);
+    }
+    let exists = path.exists();
+    if exists && !overwrite {
+        if !config.update {
+            Event::Skipped {
+                path,
+                reason: "file already exists, use --overwrite to overwrite",
+            }
+            .log(config);
+            return Ok(FileStatus::Skipped);
+        }
+        let integrity = integrity.map_or_else(|| integrity_hash(contents), str::to_string);
+        if fs::read(path).is_ok_and(|existing| integrity_hash(&existing) == integrity) {
+            Event::Skipped {
+                path,
+                reason: "unchanged",
+            }
+            .log(config);
+            return Ok(FileStatus::Skipped);
+        }
+    }
+    if !config.dry_run
+        && let Err(source) = path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|()| write_atomically(path, contents))
+    {
+        return Err(DownloadError::Io {
+            message: format!("Error writing {description} '{}'", path.display()),
+            source,
+        });
+    }
+    Event::Wrote {
+        description,
+        path,
+        bytes: contents.len(),
+        dry_run: config.dry_run,
+    }
+    .log(config);
+    Ok(if exists {
+        FileStatus::Updated
+    } else {
+        FileStatus::Written
+    })
+}
+
+/// Write a compressed copy of an output file next to it in every precompression format in the config.
+/// Each copy is written like any other output file, so it's skipped if it already exists unless `overwrite` is set.
+fn write_precompressed(
+    path: &Path,
+    contents: &[u8],
+    description: &str,
+    overwrite: bool,
+    config: &Config,
+    output: &mut Output,
+) -> Result<(), DownloadError> {
+    for compression in &config.precompress {
+        let compressed = compression
+            .compress(contents)
+            .map_err(|source| DownloadError::Io {
+                message: format!("Error compressing {description} '{}'", path.display()),
+                source,
+            })?;
+        let mut compressed_path = path.as_os_str().to_owned();
+        compressed_path.push(compression.to_extension());
+        write_output_file(
+            Path::new(&compressed_path),
+            &compressed,
+            None,
+            &format!("compressed {description}"),
+            overwrite,
+            config,
+            output,
+        )?;
+    }
+    Ok(())
+}
+
+/// Write a CSS file, along with its precompressed copies.
+fn write_css_file(
+    path: &Path,
+    css_content: &str,
+    description: &str,
+    config: &Config,
+    output: &mut Output,
+) -> Result<(), DownloadError> {
+    let contents = css_content.as_bytes();
+    write_output_file(
+        path,
+        contents,
+        None,
+        description,
+        config.overwrite_css,
+        config,
+        output,
+    )?;
+    write_precompressed(
+        path,
+        contents,
+        description,
+        config.overwrite_css,
+        config,
+        output,
+    )
+}
+
+fn write_font_css(
+    downloaded_font: &DownloadedFont,
+    config: &Config,
+    output: &mut Output,
+) -> Result<(), DownloadError> {
+    let css_filename = downloaded_font
+        .font
+        .get_css_filename(&config.filename_template);
+    let css_content = downloaded_font.get_new_css(config);
+
+    Event::Debug {
+        message: format!("  Writing CSS file with updated font path: {css_filename}"),
+    }
+    .log(config);
+
+    let css_output_path = config.css_dir().join(&css_filename);
+    write_css_file(&css_output_path, &css_content, "CSS file", config, output)
+}
+
+/// Join the rewritten CSS of every font into one stylesheet.
+fn combine_css<'a>(fonts: impl IntoIterator<Item = &'a DownloadedFont>, config: &Config) -> String {
+    let mut css = String::new();
+    for downloaded_font in fonts {
+        let _ = writeln!(css, "{}", downloaded_font.get_new_css(config));
+    }
+    css
+}
+
+/// Families that are in Google Fonts' serif category but don't have "Serif" in their name.
+const SERIF_FAMILIES: [&str; 10] = [
+    "Merriweather",
+    "Playfair Display",
+    "Lora",
+    "Libre Baskerville",
+    "EB Garamond",
+    "Crimson Text",
+    "Cormorant",
+    "Bitter",
+    "Domine",
+    "Cardo",
+];
+
+/// A generic font stack to fall back on while a family loads, guessed from its name.
+fn generic_fallback(family: &str) -> &'static str {
+    let words: Vec<&str> = family.split_whitespace().collect();
+    if words
+        .iter()
+        .any(|word| ["Mono", "Code", "Courier"].contains(word))
+    {
+        "ui-monospace, monospace"
+    } else if (words.contains(&"Serif") && !words.contains(&"Sans"))
+        || SERIF_FAMILIES.contains(&family)
+    {
+        "ui-serif, Georgia, serif"
+    } else {
+        "system-ui, sans-serif"
+    }
+}
+
+/// Build a `.font-<family>` rule for every family, using the family with a generic fallback stack.
+fn fallback_stack_css<'a>(fonts: impl IntoIterator<Item = &'a DownloadedFont>) -> String {
+    let mut css = String::new();
+    let mut seen_families = HashSet::new();
+    for downloaded_font in fonts {
+        let family = &downloaded_font.font.family;
+        if seen_families.insert(family) {
+            let _ = writeln!(
+                css,
+                ".font-{} {{\n  font-family: '{family}', {};\n}}",
+                normalize_family(family),
+                generic_fallback(family)
+            );
+        }
+    }
+    css
+}
+
+fn write_combined_css(
+    fonts: &[DownloadedFont],
+    css_filename: &str,
+    config: &Config,
+    output: &mut Output,
+) -> Result<(), DownloadError> {
+    let mut css_content = combine_css(fonts, config);
+    if config.fallback_stack {
+        css_content.push_str(&fallback_stack_css(fonts));
+    }
+    let css_output_path = config.css_dir().join(css_filename);
+    write_css_file(
+        &css_output_path,
+        &css_content,
+        "combined CSS file",
+        config,
+        output,
+    )
+}
+
+/// Build a stylesheet that imports the CSS file of every font.
+/// The CSS files are in the same directory as the index, so they're imported by filename.
+fn index_css(fonts: &[DownloadedFont], config: &Config) -> String {
+    let mut css = String::new();
+    for css_filename in css_filenames(fonts, config) {
+        let _ = writeln!(css, "@import url(\"{css_filename}\");");
+    }
+    css
+}
+
+/// The CSS file a font is written to, when there's more than one CSS file.
+fn font_css_filename(font: &FontInfo, config: &Config) -> String {
+    match config.css_grouping() {
+        CssGrouping::PerFamily => format!("{}.css", normalize_family(&font.family)),
+        _ => font.get_css_filename(&config.filename_template),
+    }
+}
+
+/// Every CSS file the fonts are written to, in the order of the fonts without any repeats.
+fn css_filenames(fonts: &[DownloadedFont], config: &Config) -> Vec<String> {
+    let mut seen_filenames = HashSet::new();
+    fonts
+        .iter()
+        .map(|downloaded_font| font_css_filename(&downloaded_font.font, config))
+        .filter(|css_filename| seen_filenames.insert(css_filename.clone()))
+        .collect()
+}
+
+/// Write a CSS file for every family, with every block in the family.
+fn write_family_css(
+    fonts: &[DownloadedFont],
+    config: &Config,
+    output: &mut Output,
+) -> Result<(), DownloadError> {
+    for css_filename in css_filenames(fonts, config) {
+        let family_fonts = fonts.iter().filter(|downloaded_font| {
+            font_css_filename(&downloaded_font.font, config) == css_filename
+        });
+        let mut css_content = combine_css(family_fonts.clone(), config);
+        if config.fallback_stack {
+            css_content.push_str(&fallback_stack_css(family_fonts));
+        }
+        write_css_file(
+            &config.css_dir().join(&css_filename),
+            &css_content,
+            "family CSS file",
+            config,
+            output,
+        )?;
+    }
+    Ok(())
+}
+
+fn write_index_css(
+    fonts: &[DownloadedFont],
+    css_filename: &str,
+    config: &Config,
+    output: &mut Output,
+) -> Result<(), DownloadError> {
+    let mut css_content = index_css(fonts, config);
+    // Per-family files already have their own rules, which the index imports
+    if config.fallback_stack && config.css_grouping() == CssGrouping::PerBlock {
+        css_content.push_str(&fallback_stack_css(fonts));
+    }
+    let css_output_path = config.css_dir().join(css_filename);
+    write_css_file(
+        &css_output_path,
+        &css_content,
+        "index CSS file",
+        config,
+        output,
+    )
+}
+
+fn write_manifest(
+    fonts: &[DownloadedFont],
+    manifest_path: &Path,
+    config: &Config,
+) -> Result<(), DownloadError> {
+    let entries = fonts
+        .iter()
+        .flat_map(|downloaded_font| {
+            downloaded_font
+                .files
+                .iter()
+                .map(|file| ManifestEntry::new(&downloaded_font.font, file))
+        })
+        .collect();
+    let manifest = Manifest {
+        summary: Summary::new(config.urls.len(), fonts),
+        fonts: entries,
+    };
+    let manifest = match config.manifest_format {
+        ManifestFormat::Json => serde_json::to_string_pretty(&manifest)?,
+        ManifestFormat::Csv => manifest_csv(&manifest.fonts),
+        ManifestFormat::Toml => toml::to_string(&manifest)?,
+    };
+    write_report_file(manifest_path, manifest.as_bytes(), "manifest", config)
+}
+
+/// Build a CSV table of the manifest entries with a header row, quoting any field with a comma, quote or newline in it.
+fn manifest_csv(entries: &[ManifestEntry]) -> String {
+    let field = |value: &str| {
+        if value.contains([',', '"', '\n', '\r']) {
+            format!("\"{}\"", value.replace('"', "\"\""))
+        } else {
+            value.to_string()
+        }
+    };
+    let mut csv = String::from(
+        "family,style,weight,stretch,writing_system,url,filename,format,bytes,status,integrity\n",
+    );
+    for entry in entries {
+        let row = [
+            field(&entry.family),
+            field(&entry.style),
+            field(&entry.weight),
+            field(entry.stretch.as_deref().unwrap_or_default()),
+            field(&entry.writing_system),
+            field(&entry.url),
+            field(&entry.filename),
+            field(&entry.format),
+            entry.bytes.to_string(),
+            entry.status.to_str().to_string(),
+            field(entry.integrity.as_deref().unwrap_or_default()),
+        ];
+        let _ = writeln!(csv, "{}", row.join(","));
+    }
+    csv
+}
+
+/// Write a file by writing it to a `.part` file next to it and then renaming it,
+/// so a crash or Ctrl-C never leaves a truncated file behind.
+fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut part_path = path.as_os_str().to_owned();
+    part_path.push(".part");
+    let part_path = PathBuf::from(part_path);
+    fs::write(&part_path, contents)
+        .and_then(|()| fs::rename(&part_path, path))
+        .inspect_err(|_| {
+            let _ = fs::remove_file(&part_path);
+        })
+}
+
+/// The name of an output file in the zip archive, which is its path relative to the CSS directory,
+/// so the CSS finds the font files in the same place when the archive is unpacked.
+/// A font file outside the CSS directory goes in the directory the fonts prefix points to, if it's a relative path inside it.
+fn zip_entry_name(path: &Path, config: &Config) -> String {
+    let relative_to = |dir: &Path| {
+        path.strip_prefix(dir).ok().map(|relative| {
+            relative
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/")
+        })
+    };
+    if let Some(name) = relative_to(config.css_dir()) {
+        return name;
+    }
+    let name = relative_to(&config.output_dir).unwrap_or_else(|| {
+        path.file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned()
+    });
+    let font_prefix = url_prefix(&config.fonts_prefix_in_css);
+    let font_prefix = font_prefix.trim_start_matches("./").trim_start_matches('.');
+    if font_prefix.is_empty()
+        || font_prefix.starts_with('/')
+        || font_prefix.contains(':')
+        || font_prefix.split('/').any(|segment| segment == "..")
+    {
+        name
+    } else {
+        format!("{font_prefix}/{name}")
+    }
+}
+
+/// Finish the zip archive and write it to its path, if the output is one.
+fn write_zip(output: Output, config: &Config) -> Result<(), DownloadError> {
+    let (Output::Zip(zip), Some(zip_path)) = (output, &config.zip) else {
+        return Ok(());
+    };
+    let archive = zip.finish().map_err(|source| DownloadError::Io {
+        message: format!("Error finishing zip archive '{}'", zip_path.display()),
+        source: source.into(),
+    })?;
+    write_report_file(zip_path, archive.get_ref(), "zip archive", config)
+}
+
+/// Write a file describing the whole run, like the manifest, which is always overwritten.
+fn write_report_file(
+    path: &Path,
+    contents: &[u8],
+    description: &str,
+    config: &Config,
+) -> Result<(), DownloadError> {
+    if !config.dry_run
+        && let Err(source) = write_atomically(path, contents)
+    {
+        return Err(DownloadError::Io {
+            message: format!("Error writing {description} '{}'", path.display()),
+            source,
+        });
+    }
+    Event::Wrote {
+        description,
+        path,
+        bytes: contents.len(),
+        dry_run: config.dry_run,
+    }
+    .log(config);
+    Ok(())
+}
+
+/// Build `<link rel="preload">` tags for every font file, grouped by family.
+fn preload_html(fonts: &[DownloadedFont], font_prefix: &str) -> String {
+    let font_prefix = url_prefix(font_prefix);
+    let mut families: Vec<(String, Vec<String>)> = Vec::new();
+    let mut seen_filenames = HashSet::new();
+    for downloaded_font in fonts {
+        let family = &downloaded_font.font.family;
+        let family_index = families
+            .iter()
+            .position(|(name, _)| name == family)
+            .unwrap_or_else(|| {
+                families.push((family.clone(), Vec::new()));
+                families.len() - 1
+            });
+        for file in &downloaded_font.files {
+            if !seen_filenames.insert(file.filename.clone()) {
+                continue;
+            }
+            let integrity = file
+                .integrity
+                .as_ref()
+                .map(|integrity| format!(" integrity=\"{integrity}\""))
+                .unwrap_or_default();
+            families[family_index].1.push(format!(
+                "<link rel=\"preload\" as=\"font\" type=\"{}\" href=\"{font_prefix}/{}\"{integrity} crossorigin>",
+                file.format.to_mime(),
+                file.filename
+            ));
+        }
+    }
+    let mut html = String::new();
+    for (family, links) in families {
+        let _ = writeln!(html, "<!-- {family} -->\n{}", links.join("\n"));
+    }
+    html
+}
+
+fn write_preload_html(
+    fonts: &[DownloadedFont],
+    preload_path: &Path,
+    config: &Config,
+) -> Result<(), DownloadError> {
+    let html = preload_html(fonts, &config.fonts_prefix_in_css);
+    write_report_file(preload_path, html.as_bytes(), "preload HTML", config)
+}
+
+/// Build a SCSS variable for every font file, like `$font-roboto-400-normal-latin: "./roboto-400-normal-latin.woff2";`, grouped by family.
+fn scss_vars(fonts: &[DownloadedFont], font_prefix: &str) -> String {
+    let font_prefix = url_prefix(font_prefix);
+    let mut families: Vec<(String, Vec<String>)> = Vec::new();
+    let mut seen_filenames = HashSet::new();
+    let mut seen_names = HashSet::new();
+    for downloaded_font in fonts {
+        let family = &downloaded_font.font.family;
+        let family_index = families
+            .iter()
+            .position(|(name, _)| name == family)
+            .unwrap_or_else(|| {
+                families.push((family.clone(), Vec::new()));
+                families.len() - 1
+            });
+        for file in &downloaded_font.files {
+            if !seen_filenames.insert(file.filename.clone()) {
+                continue;
+            }
+            // Name the variable after the path without its extension, only adding the extension if
+            // another format of the same font already took the name
+            let (stem, extension) = file
+                .filename
+                .rsplit_once('.')
+                .unwrap_or((&file.filename, ""));
+            let to_identifier = |name: &str| -> String {
+                name.chars()
+                    .map(|c| {
+                        if c.is_ascii_alphanumeric() || c == '_' {
+                            c.to_ascii_lowercase()
+                        } else {
+                            '-'
+                        }
+                    })
+                    .collect()
+            };
+            let mut name = to_identifier(stem);
+            if !seen_names.insert(name.clone()) {
+                name = format!("{name}-{}", to_identifier(extension));
+                seen_names.insert(name.clone());
+            }
+            families[family_index].1.push(format!(
+                "$font-{name}: \"{font_prefix}/{}\";",
+                file.filename
+            ));
+        }
+    }
+    let mut scss = String::new();
+    for (family, variables) in families {
+        let _ = writeln!(scss, "// {family}\n{}", variables.join("\n"));
+    }
+    scss
+}
+
+fn write_scss_vars(
+    fonts: &[DownloadedFont],
+    scss_path: &Path,
+    config: &Config,
+) -> Result<(), DownloadError> {
+    let scss = scss_vars(fonts, &config.fonts_prefix_in_css);
+    write_report_file(scss_path, scss.as_bytes(), "SCSS variables file", config)
+}
+
+/// The sentence every font is shown with in the preview page.
+const PANGRAM: &str = "The quick brown fox jumps over the lazy dog 0123456789";
+
+/// Build an HTML page linking the stylesheets and showing the pangram in every family, weight and style.
+fn preview_html(fonts: &[DownloadedFont], stylesheets: &[String]) -> String {
+    let escape = |text: &str| {
+        text.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;")
+    };
+    let mut html = String::from(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Font preview</title>\n",
+    );
+    for stylesheet in stylesheets {
+        let _ = writeln!(
+            html,
+            "<link rel=\"stylesheet\" href=\"{}\">",
+            escape(stylesheet)
+        );
+    }
+    html.push_str("</head>\n<body>\n");
+    // Each subset of a font is a separate block in the CSS, but they only need one sample between them
+    let mut seen_fonts = HashSet::new();
+    for DownloadedFont { font, .. } in fonts {
+        let style = font.normalized_style();
+        if !seen_fonts.insert((&font.family, style.clone(), &font.weight, &font.stretch)) {
+            continue;
+        }
+        // A variable font is shown at the lightest weight and narrowest width it covers
+        let first_value = |value: &str| {
+            value
+                .split_whitespace()
+                .next()
+                .unwrap_or_default()
+                .to_string()
+        };
+        let mut css = format!(
+            "font-family: '{}'; font-style: {style}; font-weight: {}",
+            font.family.replace('\'', "\\'"),
+            first_value(&font.weight)
+        );
+        let mut label = format!("{} {} {style}", font.family, font.weight);
+        if let Some(stretch) = &font.stretch {
+            let _ = write!(css, "; font-stretch: {}", first_value(stretch));
+            let _ = write!(label, " {stretch}");
+        }
+        let _ = writeln!(
+            html,
+            "<h2>{}</h2>\n<p style=\"{}\">{PANGRAM}</p>",
+            escape(&label),
+            escape(&css)
+        );
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+/// Write the preview page, linking the combined or index CSS file if there is one, or every font's CSS file.
+/// The stylesheets are linked by absolute `file://` URLs, so the page can be opened from anywhere.
+fn write_preview(
+    fonts: &[DownloadedFont],
+    preview_path: &Path,
+    config: &Config,
+) -> Result<(), DownloadError> {
+    let css_filenames: Vec<String> = match (config.combined_css_filename(), &config.index_css) {
+        (Some(css_filename), _) => vec![css_filename.to_string()],
+        (None, Some(css_filename)) => vec![css_filename.clone()],
+        (None, None) => css_filenames(fonts, config),
+    };
+    let stylesheets: Vec<String> = css_filenames
+        .iter()
+        .map(|css_filename| {
+            let css_path = config.css_dir().join(css_filename);
+            std::path::absolute(&css_path)
+                .ok()
+                .and_then(|css_path| reqwest::Url::from_file_path(css_path).ok())
+                .map_or_else(|| css_path.display().to_string(), String::from)
+        })
+        .collect();
+    let html = preview_html(fonts, &stylesheets);
+    write_report_file(preview_path, html.as_bytes(), "preview page", config)
+}
+
+/// The HTTP client used for both the CSS and the font files, which waits before each request if there's a rate limit.
+#[derive(Debug)]
+struct Client {
+    http: Box<dyn Http>,
+    rate_limiter: Option<RateLimiter>,
+}
+
+impl Client {
+    /// Wait until the rate limit allows another request to be sent.
+    async fn throttle(&self) {
+        if let Some(rate_limiter) = &self.rate_limiter {
+            let delay = rate_limiter.reserve(Instant::now());
+            if !delay.is_zero() {
+                tokio::time::sleep(delay).await;
+            }
+        }
+    }
+}
+
+/// A token bucket that lets `rate` requests through per second, in bursts of up to a second's worth.
+#[derive(Debug)]
+struct RateLimiter {
+    rate: f64,
+    capacity: f64,
+    /// The number of tokens in the bucket and when it was last refilled.
+    bucket: Mutex<(f64, Instant)>,
+}
+
+impl RateLimiter {
+    fn new(rate: f64) -> Self {
+        let capacity = rate.max(1.0);
+        Self {
+            rate,
+            capacity,
+            bucket: Mutex::new((capacity, Instant::now())),
+        }
+    }
+
+    /// Take a token from the bucket, returning how long to wait from `now` before sending the request.
+    /// The bucket can go negative, which reserves tokens that haven't been added yet, so waiting requests are sent in order.
+    fn reserve(&self, now: Instant) -> Duration {
+        let mut bucket = self.bucket.lock().unwrap_or_else(PoisonError::into_inner);
+        let (tokens, refilled) = *bucket;
+        let tokens = (tokens + now.saturating_duration_since(refilled).as_secs_f64() * self.rate)
+            .min(self.capacity)
+            - 1.0;
+        *bucket = (tokens, now.max(refilled));
+        Duration::from_secs_f64((-tokens).max(0.0) / self.rate)
+    }
+}
+
+/// Build the HTTP client used for both the CSS and the font files.
+/// Without a proxy in the config, the `HTTP_PROXY`, `HTTPS_PROXY` and `ALL_PROXY` environment variables are used.
+fn build_client(config: &Config) -> Result<Client, DownloadError> {
+    let mut builder = reqwest::Client::builder().timeout(config.timeout);
+    if let Some(proxy_url) = &config.proxy {
+        let invalid_proxy = |reason: String| DownloadError::InvalidProxy {
+            url: proxy_url.clone(),
+            reason,
+        };
+        let url = reqwest::Url::parse(proxy_url).map_err(|e| invalid_proxy(e.to_string()))?;
+        if !["http", "https", "socks5", "socks5h"].contains(&url.scheme()) {
+            return Err(invalid_proxy(
+                "the scheme must be one of http, https, socks5 or socks5h".to_string(),
+            ));
+        }
+        let proxy = reqwest::Proxy::all(url).map_err(|e| invalid_proxy(e.to_string()))?;
+        builder = builder.proxy(proxy);
+    }
+    Ok(Client {
+        http: Box::new(builder.build().map_err(DownloadError::Client)?),
+        rate_limiter: config.rate_limit.map(RateLimiter::new),
+    })
+}
+
+/// Download and parse the CSS from every URL in the config, without downloading any font files or writing anything.
+///
+/// # Errors
+///
+/// Returns an error if a CSS file can't be downloaded, or if none of its fonts are in the requested subsets.
+pub async fn list(config: &Config) -> Result<Vec<FontInfo>, DownloadError> {
+    let client = build_client(config)?;
+    let fonts: Vec<FontInfo> = fetch_all_fonts(config, &client, false)
+        .await?
+        .into_iter()
+        .flat_map(|fetched| fetched.fonts)
+        .collect();
+    warn_missing_families(config, &fonts);
+    Ok(fonts)
+}
+
+/// Download and parse the CSS from every URL in the config, returning the URL of every font file that would be downloaded, without downloading them.
+///
+/// # Errors
+///
+/// Returns an error if a CSS file can't be downloaded, or if none of its fonts are in the requested subsets.
+pub async fn font_urls(config: &Config) -> Result<Vec<String>, DownloadError> {
+    font_urls_with(config, &build_client(config)?).await
+}
+
+/// Like [`font_urls`], but downloading the CSS with an existing client.
+async fn font_urls_with(config: &Config, client: &Client) -> Result<Vec<String>, DownloadError> {
+    let mut font_urls = Vec::new();
+    let mut seen_urls = HashSet::new();
+    let mut downloaded_files = DownloadedFiles::default();
+    for fetched in fetch_all_fonts(config, client, false).await? {
+        let (_, font_sources) =
+            prepare_fonts(fetched.fonts, fetched.label, &mut downloaded_files, config)?;
+        font_urls.extend(
+            font_sources
+                .into_iter()
+                .flatten()
+                .map(|(url, _)| url)
+                .filter(|url| seen_urls.insert(url.clone())),
+        );
+    }
+    Ok(font_urls)
+}
+
+/// Download and parse the CSS from every URL in the config, returning it all rewritten to point at where the font files
+/// would be, as one stylesheet, without downloading the font files or writing anything.
+///
+/// # Errors
+///
+/// Returns an error if a CSS file can't be downloaded, or if none of its fonts are in the requested subsets.
+pub async fn rewritten_css(config: &Config) -> Result<String, DownloadError> {
+    rewritten_css_with(config, &build_client(config)?).await
+}
+
+/// Like [`rewritten_css`], but downloading the CSS with an existing client.
+async fn rewritten_css_with(config: &Config, client: &Client) -> Result<String, DownloadError> {
+    let mut fonts = Vec::new();
+    let mut downloaded_files = DownloadedFiles::default();
+    for fetched in fetch_all_fonts(config, client, true).await? {
+        let (url_fonts, font_sources) =
+            prepare_fonts(fetched.fonts, fetched.label, &mut downloaded_files, config)?;
+        for (font, sources) in url_fonts.into_iter().zip(font_sources) {
+            let files = planned_font_files(&font, sources, fetched.label, config);
+            fonts.push(DownloadedFont { font, files });
+        }
+    }
+    // Sorted like the combined CSS file, so the output is the same as it would've been
+    fonts.sort_by(|a, b| compare_fonts(&a.font, &b.font));
+    let mut css = combine_css(&fonts, config);
+    if config.fallback_stack {
+        css.push_str(&fallback_stack_css(&fonts));
+    }
+    Ok(css)
+}
+
+/// Check the font files in the output directory against the sizes Google Fonts reports for them, without downloading or writing anything.
+///
+/// # Errors
+///
+/// Returns an error if a CSS file can't be downloaded, or if Google Fonts can't be asked for a font file's size.
+pub async fn verify(config: &Config) -> Result<Vec<VerifiedFile>, DownloadError> {
+    let client = build_client(config)?;
+    let mut files = Vec::new();
+    let mut downloaded_files = DownloadedFiles::default();
+    for fetched in fetch_all_fonts(config, &client, false).await? {
+        let (fonts, font_sources) =
+            prepare_fonts(fetched.fonts, fetched.label, &mut downloaded_files, config)?;
+        for (font, sources) in fonts.iter().zip(font_sources) {
+            for (url, format) in sources {
+                let filename = font_file_path(font, &format, fetched.label, config);
+                files.push((url, filename));
+            }
+        }
+    }
+
+    stream::iter(files)
+        .map(|(url, filename)| {
+            let client = &client;
+            async move {
+                let local_size = fs::metadata(config.output_dir.join(&filename))
+                    .ok()
+                    .map(|metadata| metadata.len());
+                let status = match local_size {
+                    None => VerifyStatus::Missing,
+                    Some(local) => match fetch_size(client, &url, config).await? {
+                        None => VerifyStatus::UnknownSize,
+                        Some(remote) if remote == local => VerifyStatus::Matches,
+                        Some(remote) => VerifyStatus::SizeMismatch { local, remote },
+                    },
+                };
+                Ok(VerifiedFile {
+                    url,
+                    filename,
+                    status,
+                })
+            }
+        })
+        .buffered(config.jobs.get())
+        .try_collect()
+        .await
+}
+
+/// Download the fonts from every URL in the config, writing the font files, CSS and manifest to the output directory.
+///
+/// # Errors
+///
+/// Returns an error if a CSS or font file can't be downloaded, or if an output file can't be written.
+pub async fn download(config: &Config) -> Result<Vec<DownloadedFont>, DownloadError> {
+    // Create the output directories if they don't exist, which aren't needed if everything goes in a zip archive
+    if !config.dry_run && config.zip.is_none() {
+        ensure_output_dir(&config.output_dir, "output directory", config)?;
+        if !config.no_css {
+            ensure_output_dir(config.css_dir(), "CSS output directory", config)?;
+        }
+    }
+
+    // Create a reusable HTTP client
+    let client = build_client(config)?;
+
+    // Download the CSS from several URLs at once, and then the fonts from each URL in turn
+    let fetched: Vec<(&str, Result<FetchedCss, DownloadError>)> = if config.keep_going {
+        fetch_each_css(config, &client, true).collect().await
+    } else {
+        fetch_each_css(config, &client, true)
+            .map(|(url, fetched)| fetched.map(|fetched| (url, Ok(fetched))))
+            .try_collect()
+            .await?
+    };
+    let mut fonts = Vec::new();
+    let mut failures = Vec::new();
+    let mut downloaded_files = DownloadedFiles::default();
+    let mut output = Output::new(config);
+    for (url, fetched) in fetched {
+        let url_fonts = match fetched {
+            Ok(fetched) => {
+                download_parsed_fonts(
+                    fetched.fonts,
+                    fetched.not_modified,
+                    fetched.label,
+                    config,
+                    &client,
+                    &mut downloaded_files,
+                    &mut output,
+                )
+                .await
+            }
+            Err(error) => Err(error),
+        };
+        match url_fonts {
+            Ok(url_fonts) => fonts.extend(url_fonts),
+            // Carry on with the other URLs, and report every failure at the end
+            Err(error) if config.keep_going => {
+                Event::Error {
+                    message: error.to_string(),
+                }
+                .log(config);
+                failures.push(UrlFailure {
+                    url: url.to_string(),
+                    error,
+                });
+            }
+            Err(error) => return Err(error),
+        }
+    }
+    warn_missing_families(
+        config,
+        fonts.iter().map(|downloaded_font| &downloaded_font.font),
+    );
+    // Google Fonts doesn't promise an order of faces, so sort the fonts to keep the combined CSS and manifest stable,
+    // keeping the order of the subsets within each face
+    fonts.sort_by(|a, b| compare_fonts(&a.font, &b.font));
+
+    if let Some(css_filename) = config.combined_css_filename()
+        && !config.no_css
+    {
+        write_combined_css(&fonts, css_filename, config, &mut output)?;
+    }
+
+    if config.css_grouping() == CssGrouping::PerFamily && !config.no_css {
+        write_family_css(&fonts, config, &mut output)?;
+    }
+
+    if let Some(css_filename) = &config.index_css
+        && config.css_grouping() != CssGrouping::Single
+        && !config.no_css
+    {
+        write_index_css(&fonts, css_filename, config, &mut output)?;
+    }
+
+    write_zip(output, config)?;
+
+    if let Some(manifest_path) = &config.manifest {
+        write_manifest(&fonts, manifest_path, config)?;
+    }
+
+    if let Some(preload_path) = &config.preload_html {
+        write_preload_html(&fonts, preload_path, config)?;
+    }
+
+    if let Some(scss_path) = &config.scss_vars {
+        write_scss_vars(&fonts, scss_path, config)?;
+    }
+
+    if let Some(preview_path) = &config.preview {
+        write_preview(&fonts, preview_path, config)?;
+    }
+
+    if !failures.is_empty() {
+        return Err(DownloadError::UrlsFailed { fonts, failures });
+    }
+    if !downloaded_files.rejected.is_empty() {
+        return Err(DownloadError::InvalidFontFiles {
+            fonts,
+            urls: downloaded_files.rejected,
+        });
+    }
+    Ok(fonts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    fn parse_fonts(css: &str) -> Vec<FontInfo> {
+        split_css_into_fonts(css)
+            .into_iter()
+            .map(Result::unwrap)
+            .collect()
+    }
+
+    /// A normal, regular font in the family, for tests that only care about the family.
+    fn test_font(family: &str) -> FontInfo {
+        FontInfo::parse(
+            &format!(
+                "@font-face {{\n  font-family: '{family}';\n  font-style: normal;\n  font-weight: 400;\n  src: url(https://fonts.gstatic.com/s/font.woff2) format('woff2');\n}}"
+            ),
+            "latin",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_parse_fonts() {
+        let css = r"/* latin */
+@font-face {
+  font-family: 'Creepster';
+  font-style: normal;
+  font-weight: 400;
+  font-display: swap;
+  src: url(https://fonts.gstatic.com/s/creepster/v13/AlZy_zVUqJz4yMrniH4Rcn35fh4Dog.woff2) format('woff2');
+  unicode-range: U+0000-00FF, U+0131, U+0152-0153, U+02BB-02BC, U+02C6, U+02DA, U+02DC, U+0304, U+0308, U+0329, U+2000-206F, U+20AC, U+2122, U+2191, U+2193, U+2212, U+2215, U+FEFF, U+FFFD;
+}
+/* latin */
+@font-face {
+  font-family: 'Gravitas One';
+  font-style: normal;
+  font-weight: 400;
+  font-display: swap;
+  src: url(https://fonts.gstatic.com/s/gravitasone/v19/5h1diZ4hJ3cblKy3LWakKQmqCm5MjXPjbA.woff2) format('woff2');
+  unicode-range: U+0000-00FF, U+0131, U+0152-0153, U+02BB-02BC, U+02C6, U+02DA, U+02DC, U+0304, U+0308, U+0329, U+2000-206F, U+20AC, U+2122, U+2191, U+2193, U+2212, U+2215, U+FEFF, U+FFFD;
+}";
+
+        let result = parse_fonts(css);
+        assert_eq!(result.len(), 2);
+        assert_eq!(result[0].writing_system_name, "latin");
+        assert_eq!(
+            result[0].css,
+            "@font-face {\n  font-family: 'Creepster';\n  font-style: normal;\n  font-weight: 400;\n  font-display: swap;\n  src: url(https://fonts.gstatic.com/s/creepster/v13/AlZy_zVUqJz4yMrniH4Rcn35fh4Dog.woff2) format('woff2');\n  unicode-range: U+0000-00FF, U+0131, U+0152-0153, U+02BB-02BC, U+02C6, U+02DA, U+02DC, U+0304, U+0308, U+0329, U+2000-206F, U+20AC, U+2122, U+2191, U+2193, U+2212, U+2215, U+FEFF, U+FFFD;\n}"
+        );
+        assert_eq!(result[1].writing_system_name, "latin");
+        assert_eq!(
+            result[1].css,
+            "@font-face {\n  font-family: 'Gravitas One';\n  font-style: normal;\n  font-weight: 400;\n  font-display: swap;\n  src: url(https://fonts.gstatic.com/s/gravitasone/v19/5h1diZ4hJ3cblKy3LWakKQmqCm5MjXPjbA.woff2) format('woff2');\n  unicode-range: U+0000-00FF, U+0131, U+0152-0153, U+02BB-02BC, U+02C6, U+02DA, U+02DC, U+0304, U+0308, U+0329, U+2000-206F, U+20AC, U+2122, U+2191, U+2193, U+2212, U+2215, U+FEFF, U+FFFD;\n}"
+        );
+    }
+
+    #[test]
+    fn test_split_css_without_comments() {
+        let blocks = split_css_blocks(
+            "@font-face {
+  font-family: 'Roboto';
+  font-weight: 400;
+}
+@font-face {
+  font-family: 'Roboto';
+  font-weight: 700;
+}
+",
+        );
+        assert_eq!(
+            blocks,
+            vec![
+                (
+                    "",
+                    "@font-face {\n  font-family: 'Roboto';\n  font-weight: 400;\n}"
+                ),
+                (
+                    "",
+                    "@font-face {\n  font-family: 'Roboto';\n  font-weight: 700;\n}"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_split_css_with_several_blocks_after_a_comment() {
+        let blocks = split_css_blocks(
+            "/* latin */
+@font-face {
+  font-weight: 400;
+}
+@font-face {
+  font-weight: 700;
+}
+",
+        );
+        assert_eq!(
+            blocks,
+            vec![
+                ("latin", "@font-face {\n  font-weight: 400;\n}"),
+                ("latin", "@font-face {\n  font-weight: 700;\n}"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_split_css_keeps_trailing_block() {
+        let blocks = split_css_blocks(
+            "/* cyrillic */
+@font-face {
+  font-weight: 400;
+}
+/* latin */
+@font-face {
+  font-weight: 400;
+}
+@font-face {
+  font-weight: 700;
+}",
+        );
+        let subsets: Vec<&str> = blocks.iter().map(|(subset, _)| *subset).collect();
+        assert_eq!(subsets, vec!["cyrillic", "latin", "latin"]);
+        assert_eq!(blocks[2].1, "@font-face {\n  font-weight: 700;\n}");
+    }
+
+    #[test]
+    fn test_css_v1() {
+        assert_eq!(
+            CssApi::from_url(
+                "https://fonts.googleapis.com/css?family=Roboto:400,700italic&subset=latin"
+            ),
+            CssApi::V1
+        );
+        assert_eq!(
+            CssApi::from_url("https://fonts.googleapis.com/css2?family=Roboto&display=swap"),
+            CssApi::V2
+        );
+
+        // The response to https://fonts.googleapis.com/css?family=Roboto:400,700italic&subset=latin,
+        // which has no font-display because the URL has no display parameter
+        let fonts = parse_fonts(
+            r"/* latin */
+@font-face {
+  font-family: 'Roboto';
+  font-style: italic;
+  font-weight: 700;
+  src: url(https://fonts.gstatic.com/s/roboto/v30/KFOjCnqEu92Fr1Mu51TzBic6CsTYl4BO.woff2) format('woff2');
+  unicode-range: U+0000-00FF, U+0131, U+0152-0153, U+02BB-02BC, U+02C6, U+02DA, U+02DC, U+2000-206F, U+2074, U+20AC, U+2122, U+2191, U+2193, U+2212, U+2215, U+FEFF, U+FFFD;
+}
+/* latin */
+@font-face {
+  font-family: 'Roboto';
+  font-style: normal;
+  font-weight: 400;
+  src: url(https://fonts.gstatic.com/s/roboto/v30/KFOmCnqEu92Fr1Mu4mxKKTU1Kg.woff2) format('woff2');
+  unicode-range: U+0000-00FF, U+0131, U+0152-0153, U+02BB-02BC, U+02C6, U+02DA, U+02DC, U+2000-206F, U+2074, U+20AC, U+2122, U+2191, U+2193, U+2212, U+2215, U+FEFF, U+FFFD;
+}",
+        );
+        assert_eq!(fonts.len(), 2);
+        assert_eq!(fonts[0].family, "Roboto");
+        assert_eq!(fonts[0].style, "italic");
+        assert_eq!(fonts[0].weight, "700");
+        assert_eq!(
+            fonts[0].url,
+            "https://fonts.gstatic.com/s/roboto/v30/KFOjCnqEu92Fr1Mu51TzBic6CsTYl4BO.woff2"
+        );
+        assert_eq!(fonts[1].weight, "400");
+        assert_eq!(
+            fonts[1].url,
+            "https://fonts.gstatic.com/s/roboto/v30/KFOmCnqEu92Fr1Mu4mxKKTU1Kg.woff2"
+        );
+        assert!(fonts.iter().all(|font| font.display.is_none()));
+    }
+
+    #[test]
+    fn test_parse_font_info() {
+        let font = FontInfo::parse(
+            "@font-face {
+  font-family: 'Roboto Flex';
+  font-style: normal;
+  font-weight: 100 1000;
+  font-stretch: 25% 151%;
+  font-display: swap;
+  src: local('Roboto Flex'), url(https://fonts.gstatic.com/s/robotoflex/v26/latin.woff2) format('woff2');
+  unicode-range: U+0000-00FF;
+}",
+            "latin",
+        )
+        .unwrap();
+        assert_eq!(font.family, "Roboto Flex");
+        assert_eq!(font.style, "normal");
+        assert_eq!(font.weight, "100 1000");
+        assert_eq!(font.stretch.as_deref(), Some("25% 151%"));
+        assert_eq!(font.display.as_deref(), Some("swap"));
+        assert_eq!(font.unicode_range.as_deref(), Some("U+0000-00FF"));
+        assert_eq!(
+            font.url,
+            "https://fonts.gstatic.com/s/robotoflex/v26/latin.woff2"
+        );
+        assert_eq!(font.format, FontFormat::Woff2);
+        assert_eq!(font.sources.len(), 1);
+        assert_eq!(font.writing_system_name, "latin");
+    }
+
+    #[test]
+    fn test_missing_property_is_parse_error() {
+        let error = FontInfo::parse(
+            "@font-face {\n  font-family: 'Material Icons';\n  font-weight: 400;\n  src: url(https://fonts.gstatic.com/s/materialicons/v1/icons.woff2) format('woff2');\n}",
+            "",
+        )
+        .unwrap_err();
+        assert_eq!(error.property, "font-style");
+        assert!(
+            error
+                .snippet
+                .starts_with("@font-face { font-family: 'Material Icons';")
+        );
+
+        let fonts = split_css_into_fonts(
+            "@font-face {\n  font-family: 'Roboto';\n}\n@font-face {\n  font-family: 'Roboto';\n  font-style: normal;\n  font-weight: 400;\n  src: url(https://fonts.gstatic.com/s/roboto/v47/latin.woff2) format('woff2');\n}",
+        );
+        assert_eq!(fonts.len(), 2);
+        assert_eq!(fonts[0].as_ref().unwrap_err().property, "font-style");
+        assert_eq!(fonts[1].as_ref().unwrap().family, "Roboto");
+    }
+
+    #[test]
+    fn test_combine_css() {
+        let fonts = parse_fonts(
+            r"/* cyrillic */
+@font-face {
+  font-family: 'Roboto';
+  font-style: normal;
+  font-weight: 400;
+  font-display: swap;
+  src: url(https://fonts.gstatic.com/s/roboto/v47/cyrillic.woff2) format('woff2');
+}
+/* latin */
+@font-face {
+  font-family: 'Roboto';
+  font-style: normal;
+  font-weight: 400;
+  font-display: swap;
+  src: url(https://fonts.gstatic.com/s/roboto/v47/latin.woff2) format('woff2');
+}",
+        );
+        let fonts: Vec<DownloadedFont> = fonts
+            .into_iter()
+            .map(|font| {
+                let (url, format) = (font.url.clone(), font.format.clone());
+                let filename = font.get_font_filename(&FilenameTemplate::default());
+                DownloadedFont {
+                    font,
+                    files: vec![DownloadedFile {
+                        url,
+                        format,
+                        filename,
+                        size: 0,
+                        integrity: None,
+                        status: FileStatus::Written,
+                    }],
+                }
+            })
+            .collect();
+        let combined_css = combine_css(&fonts, &Config::default());
+        assert_eq!(
+            combined_css,
+            "/* cyrillic */\n@font-face {\n  font-family: 'Roboto';\n  font-style: normal;\n  font-weight: 400;\n  font-display: swap;\n  src: url(./roboto-400-normal-cyrillic.woff2) format('woff2');\n}\n/* latin */\n@font-face {\n  font-family: 'Roboto';\n  font-style: normal;\n  font-weight: 400;\n  font-display: swap;\n  src: url(./roboto-400-normal-latin.woff2) format('woff2');\n}\n"
+        );
+
+        let config = Config {
+            subset_comments: false,
+            ..Config::default()
+        };
+        assert_eq!(
+            combine_css(&fonts, &config),
+            "@font-face {\n  font-family: 'Roboto';\n  font-style: normal;\n  font-weight: 400;\n  font-display: swap;\n  src: url(./roboto-400-normal-cyrillic.woff2) format('woff2');\n}\n@font-face {\n  font-family: 'Roboto';\n  font-style: normal;\n  font-weight: 400;\n  font-display: swap;\n  src: url(./roboto-400-normal-latin.woff2) format('woff2');\n}\n"
+        );
+
+        // Sorting keeps latin after latin-ext, so latin's face wins for the characters that are in both
+        let mut fonts: Vec<DownloadedFont> = [
+            ("latin-ext", "400"),
+            ("latin", "400"),
+            ("latin-ext", "300"),
+            ("latin", "300"),
+        ]
+        .into_iter()
+        .map(|(subset, weight)| DownloadedFont {
+            font: parse_fonts(&format!(
+                "/* {subset} */\n@font-face {{\n  font-family: 'Roboto';\n  font-style: normal;\n  font-weight: {weight};\n  src: url(https://fonts.gstatic.com/s/roboto/v47/{subset}-{weight}.woff2) format('woff2');\n}}"
+            ))
+            .remove(0),
+            files: Vec::new(),
+        })
+        .collect();
+        fonts.sort_by(|a, b| compare_fonts(&a.font, &b.font));
+        let combined_css = combine_css(&fonts, &Config::default());
+        let faces: Vec<&str> = combined_css
+            .lines()
+            .filter(|line| line.starts_with("/*") || line.starts_with("  font-weight:"))
+            .collect();
+        assert_eq!(
+            faces,
+            vec![
+                "/* latin-ext */",
+                "  font-weight: 300;",
+                "/* latin */",
+                "  font-weight: 300;",
+                "/* latin-ext */",
+                "  font-weight: 400;",
+                "/* latin */",
+                "  font-weight: 400;",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_index_css() {
+        let downloaded_font = |css: &str| DownloadedFont {
+            font: parse_fonts(css).remove(0),
+            files: Vec::new(),
+        };
+        let fonts = vec![
+            downloaded_font(
+                "/* cyrillic */\n@font-face {\n  font-family: 'Roboto';\n  font-style: normal;\n  font-weight: 400;\n  src: url(https://fonts.gstatic.com/s/roboto/v47/cyrillic.woff2) format('woff2');\n}",
+            ),
+            downloaded_font(
+                "/* latin */\n@font-face {\n  font-family: 'Roboto';\n  font-style: normal;\n  font-weight: 400;\n  src: url(https://fonts.gstatic.com/s/roboto/v47/latin.woff2) format('woff2');\n}",
+            ),
+        ];
+        assert_eq!(
+            index_css(&fonts, &Config::default()),
+            "@import url(\"roboto-400-normal-cyrillic.css\");\n@import url(\"roboto-400-normal-latin.css\");\n"
+        );
+        let config = Config {
+            css_grouping: CssGrouping::PerFamily,
+            ..Config::default()
+        };
+        assert_eq!(index_css(&fonts, &config), "@import url(\"roboto.css\");\n");
+    }
+
+    #[test]
+    fn test_fallback_stack_css() {
+        let fonts: Vec<DownloadedFont> = [
+            "Roboto",
+            "Roboto",
+            "Noto Serif",
+            "Noto Sans",
+            "Fira Code",
+            "Lora",
+        ]
+        .into_iter()
+        .map(|family| DownloadedFont {
+            font: test_font(family),
+            files: Vec::new(),
+        })
+        .collect();
+        assert_eq!(
+            fallback_stack_css(&fonts),
+            ".font-roboto {\n  font-family: 'Roboto', system-ui, sans-serif;\n}\n.font-noto-serif {\n  font-family: 'Noto Serif', ui-serif, Georgia, serif;\n}\n.font-noto-sans {\n  font-family: 'Noto Sans', system-ui, sans-serif;\n}\n.font-fira-code {\n  font-family: 'Fira Code', ui-monospace, monospace;\n}\n.font-lora {\n  font-family: 'Lora', ui-serif, Georgia, serif;\n}\n"
+        );
+        assert_eq!(
+            generic_fallback("PT Serif Caption"),
+            "ui-serif, Georgia, serif"
+        );
+        assert_eq!(generic_fallback("Noto Sans Serif"), "system-ui, sans-serif");
+        assert_eq!(generic_fallback("Roboto Mono"), "ui-monospace, monospace");
+    }
+
+    #[test]
+    fn test_css_grouping() {
+        assert_eq!(CssGrouping::parse("per-block"), Ok(CssGrouping::PerBlock));
+        assert_eq!(CssGrouping::parse("per-family"), Ok(CssGrouping::PerFamily));
+        assert_eq!(CssGrouping::parse("single"), Ok(CssGrouping::Single));
+        assert!(CssGrouping::parse("per-style").is_err());
+
+        let mut config = Config {
+            css_grouping: CssGrouping::Single,
+            ..Config::default()
+        };
+        assert_eq!(config.combined_css_filename(), Some("fonts.css"));
+        config.combine_css = Some("all.css".to_string());
+        assert_eq!(config.combined_css_filename(), Some("all.css"));
+        config.css_grouping = CssGrouping::PerFamily;
+        assert_eq!(config.css_grouping(), CssGrouping::Single);
+        assert_eq!(config.combined_css_filename(), Some("all.css"));
+    }
+
+    #[test]
+    fn test_new_css_keeps_unicode_range() {
+        let fonts = parse_fonts(
+            r"/* latin-ext */
+@font-face {
+  font-family: 'Roboto';
+  font-style: normal;
+  font-weight: 400;
+  font-display: swap;
+  src: url(https://fonts.gstatic.com/s/roboto/v47/latin-ext.woff2) format('woff2');
+  unicode-range: U+0100-02BA, U+02BD-02C5, U+02C7-02CC, U+02CE-02D7, U+02DD-02FF, U+0304, U+0308, U+0329, U+1D00-1DBF, U+1E00-1E9F, U+1EF2-1EFF, U+2020, U+20A0-20AB, U+20AD-20C0, U+2113, U+2C60-2C7F, U+A720-A7FF;
+}
+/* latin */
+@font-face {
+  font-family: 'Roboto';
+  font-style: normal;
+  font-weight: 400;
+  font-display: swap;
+  src: url(https://fonts.gstatic.com/s/roboto/v47/latin.woff2) format('woff2');
+  unicode-range: U+0000-00FF, U+0131, U+0152-0153, U+02BB-02BC, U+02C6, U+02DA, U+02DC, U+0304, U+0308, U+0329, U+2000-206F, U+20AC, U+2122, U+2191, U+2193, U+2212, U+2215, U+FEFF, U+FFFD;
+}",
+        );
+        assert_eq!(fonts.len(), 2);
+        let latin_ext_range = "U+0100-02BA, U+02BD-02C5, U+02C7-02CC, U+02CE-02D7, U+02DD-02FF, U+0304, U+0308, U+0329, U+1D00-1DBF, U+1E00-1E9F, U+1EF2-1EFF, U+2020, U+20A0-20AB, U+20AD-20C0, U+2113, U+2C60-2C7F, U+A720-A7FF";
+        let latin_range = "U+0000-00FF, U+0131, U+0152-0153, U+02BB-02BC, U+02C6, U+02DA, U+02DC, U+0304, U+0308, U+0329, U+2000-206F, U+20AC, U+2122, U+2191, U+2193, U+2212, U+2215, U+FEFF, U+FFFD";
+        assert_eq!(fonts[0].unicode_range.as_deref().unwrap(), latin_ext_range);
+        assert_eq!(fonts[1].unicode_range.as_deref().unwrap(), latin_range);
+        for (font, range) in fonts.iter().zip([latin_ext_range, latin_range]) {
+            let files = [(
+                font.get_font_filename(&FilenameTemplate::default()),
+                FontFormat::Woff2,
+            )];
+            assert!(
+                font.get_new_css(".", &files, None)
+                    .contains(&format!("unicode-range: {range};"))
+            );
+        }
+    }
+
+    #[test]
+    fn test_deduplicate_fonts() {
+        let css = r"/* latin */
+@font-face {
+  font-family: 'Roboto';
+  font-style: normal;
+  font-weight: 400;
+  font-display: swap;
+  src: url(https://fonts.gstatic.com/s/roboto/v47/first.woff2) format('woff2');
+}
+/* latin */
+@font-face {
+  font-family: 'Roboto';
+  font-style: normal;
+  font-weight: 400;
+  font-display: swap;
+  src: url(https://fonts.gstatic.com/s/roboto/v47/second.woff2) format('woff2');
+}";
+        let mut downloaded_files = DownloadedFiles::default();
+        let fonts = deduplicate_fonts(
+            parse_fonts(css),
+            None,
+            &mut downloaded_files,
+            &Config::default(),
+        );
+        assert_eq!(fonts.len(), 2);
+        assert_eq!(
+            fonts[0].get_font_filename(&FilenameTemplate::default()),
+            "roboto-400-normal-latin.woff2"
+        );
+        assert_eq!(
+            fonts[1].get_font_filename(&FilenameTemplate::default()),
+            "roboto-400-normal-latin-2.woff2"
+        );
+        assert_eq!(
+            fonts[1].get_css_filename(&FilenameTemplate::default()),
+            "roboto-400-normal-latin-2.css"
+        );
+
+        // The same CSS from another URL doesn't download anything again
+        let fonts = deduplicate_fonts(
+            parse_fonts(css),
+            None,
+            &mut downloaded_files,
+            &Config::default(),
+        );
+        assert!(fonts.is_empty());
+
+        // But each labelled URL gets its own copy in its subdirectory, without a suffix
+        for label in ["Body", "Headings"] {
+            let fonts = deduplicate_fonts(
+                parse_fonts(css),
+                Some(label),
+                &mut downloaded_files,
+                &Config::default(),
+            );
+            assert_eq!(fonts.len(), 2);
+            assert_eq!(
+                fonts[0].get_font_filename(&FilenameTemplate::default()),
+                "roboto-400-normal-latin.woff2"
+            );
+        }
+    }
+
+    #[test]
+    fn test_filename_template() {
+        let fonts = parse_fonts(
+            r"/* latin */
+@font-face {
+  font-family: 'Open Sans';
+  font-style: italic;
+  font-weight: 700;
+  font-stretch: 100%;
+  font-display: swap;
+  src: url(https://fonts.gstatic.com/s/opensans/v40/latin.woff2) format('woff2');
+}",
+        );
+        let template = FilenameTemplate::parse("{family}_{weight}{style}.{hash}.{ext}").unwrap();
+        let hash = short_hash("https://fonts.gstatic.com/s/opensans/v40/latin.woff2");
+        assert_eq!(hash.len(), 8);
+        assert_eq!(
+            fonts[0].get_font_filename(&template),
+            format!("open-sans_700italic.{hash}.woff2")
+        );
+        assert_eq!(
+            fonts[0].get_css_filename(&template),
+            format!("open-sans_700italic.{hash}.css")
+        );
+
+        // The hash algorithm and length can be changed
+        let template = FilenameTemplate::parse("{family}.{hash}.{ext}").unwrap();
+        for (algorithm, length, hash) in [
+            (HashAlgorithm::Md5, 12, "31eed05ffcbd"),
+            (HashAlgorithm::Sha1, 6, "4f76a2"),
+            (
+                HashAlgorithm::Sha256,
+                100,
+                "a2e5e4d9f53ec8f17962639091e9a8e630df321992ce9b8e3f02160f847066f5",
+            ),
+        ] {
+            assert_eq!(
+                fonts[0].get_font_filename(&template.clone().with_hash(algorithm, length)),
+                format!("open-sans.{hash}.woff2")
+            );
+        }
+        let base62_template = template
+            .clone()
+            .with_hash(HashAlgorithm::Sha256, 8)
+            .with_hash_encoding(HashEncoding::Base62);
+        let base62_hash = to_base62(&Sha256::digest(
+            b"https://fonts.gstatic.com/s/opensans/v40/latin.woff2",
+        ));
+        assert_eq!(
+            fonts[0].get_font_filename(&base62_template),
+            format!("open-sans.{}.woff2", &base62_hash[..8])
+        );
+        assert_eq!(to_base62(&[0]), "0");
+        assert_eq!(to_base62(&[0, 61]), "z");
+        assert_eq!(to_base62(&[1, 0]), "48");
+        assert_eq!(HashEncoding::parse("base62"), Ok(HashEncoding::Base62));
+        assert!(HashEncoding::parse("base64").is_err());
+        let xxhash_template = template.with_hash(HashAlgorithm::Xxhash, 8);
+        assert_eq!(
+            fonts[0].get_font_filename(&xxhash_template).len(),
+            "open-sans.12345678.woff2".len()
+        );
+        assert_eq!(HashAlgorithm::parse("md5"), Ok(HashAlgorithm::Md5));
+        assert!(HashAlgorithm::parse("crc32").is_err());
+
+        assert!(FilenameTemplate::parse("{family}-{size}.{ext}").is_err());
+        assert!(FilenameTemplate::parse("{family}-{weight").is_err());
+        assert!(FilenameTemplate::parse("{family}-{weight}").is_err());
+    }
+
+    #[test]
+    fn test_font_relative_path() {
+        let font = parse_fonts(
+            r"/* latin */
+@font-face {
+  font-family: 'Open Sans';
+  font-style: normal;
+  font-weight: 400;
+  font-display: swap;
+  src: url(https://fonts.gstatic.com/s/opensans/v40/latin.woff2) format('woff2');
+}",
+        )
+        .remove(0);
+        let relative_path = |layout| {
+            font.get_font_relative_path(&FilenameTemplate::default(), &FontFormat::Woff2, layout)
+        };
+        assert_eq!(
+            relative_path(OutputLayout::Flat),
+            "open-sans-400-normal-latin.woff2"
+        );
+        assert_eq!(
+            relative_path(OutputLayout::Family),
+            "open-sans/open-sans-400-normal-latin.woff2"
+        );
+        assert_eq!(
+            relative_path(OutputLayout::Subset),
+            "latin/open-sans-400-normal-latin.woff2"
+        );
+        assert_eq!(
+            relative_path(OutputLayout::FamilySubset),
+            "open-sans/latin/open-sans-400-normal-latin.woff2"
+        );
+
+        let files = [(relative_path(OutputLayout::FamilySubset), FontFormat::Woff2)];
+        assert!(font.get_new_css("./fonts", &files, None).contains(
+            "src: url(./fonts/open-sans/latin/open-sans-400-normal-latin.woff2) format('woff2');"
+        ));
+    }
+
+    #[test]
+    fn test_variable_font() {
+        let fonts = parse_fonts(
+            r"/* latin */
+@font-face {
+  font-family: 'Roboto';
+  font-style: normal;
+  font-weight: 100 900;
+  font-display: swap;
+  src: url(https://fonts.gstatic.com/s/roboto/v47/variable.woff2) format('woff2');
+}
+/* latin */
+@font-face {
+  font-family: 'Roboto';
+  font-style: normal;
+  font-weight: 400;
+  font-display: swap;
+  src: url(https://fonts.gstatic.com/s/roboto/v47/regular.woff2) format('woff2');
+}",
+        );
+        assert!(fonts[0].is_variable());
+        assert_eq!(
+            fonts[0].get_font_filename(&FilenameTemplate::default()),
+            "roboto-100-900-normal-latin.woff2"
+        );
+        assert!(!fonts[1].is_variable());
+        assert_eq!(
+            fonts[1].get_font_filename(&FilenameTemplate::default()),
+            "roboto-400-normal-latin.woff2"
+        );
+    }
+
+    #[test]
+    fn test_oblique_style_in_filename() {
+        let fonts = parse_fonts(
+            r"/* latin */
+@font-face {
+  font-family: 'Roboto Flex';
+  font-style: oblique 0deg 10deg;
+  font-weight: 400;
+  src: url(https://fonts.gstatic.com/s/robotoflex/v26/oblique.woff2) format('woff2');
+}
+/* latin */
+@font-face {
+  font-family: 'Roboto Flex';
+  font-style: italic;
+  font-weight: 400;
+  src: url(https://fonts.gstatic.com/s/robotoflex/v26/italic.woff2) format('woff2');
+}",
+        );
+        assert_eq!(fonts[0].style, "oblique 0deg 10deg");
+        assert_eq!(fonts[0].normalized_style(), "oblique");
+        assert_eq!(fonts[1].normalized_style(), "italic");
+        let template = FilenameTemplate::default();
+        assert_eq!(
+            fonts[0].get_font_filename(&template),
+            "roboto-flex-400-oblique-latin.woff2"
+        );
+        assert_eq!(
+            fonts[0].get_css_filename(&template),
+            "roboto-flex-400-oblique-latin.css"
+        );
+    }
+
+    #[test]
+    fn test_font_stretch_in_filename() {
+        let fonts = parse_fonts(
+            r"/* latin */
+@font-face {
+  font-family: 'Roboto Flex';
+  font-style: normal;
+  font-weight: 400;
+  font-stretch: 75%;
+  font-display: swap;
+  src: url(https://fonts.gstatic.com/s/robotoflex/v26/condensed.woff2) format('woff2');
+}
+/* latin */
+@font-face {
+  font-family: 'Roboto Flex';
+  font-style: normal;
+  font-weight: 400;
+  font-stretch: 125%;
+  font-display: swap;
+  src: url(https://fonts.gstatic.com/s/robotoflex/v26/expanded.woff2) format('woff2');
+}
+/* latin */
+@font-face {
+  font-family: 'Roboto Flex';
+  font-style: normal;
+  font-weight: 400;
+  font-display: swap;
+  src: url(https://fonts.gstatic.com/s/robotoflex/v26/normal.woff2) format('woff2');
+}",
+        );
+        let template = FilenameTemplate::default();
+        assert_eq!(
+            fonts[0].get_font_filename(&template),
+            "roboto-flex-400-normal-75pct-latin.woff2"
+        );
+        assert_eq!(
+            fonts[1].get_font_filename(&template),
+            "roboto-flex-400-normal-125pct-latin.woff2"
+        );
+        assert_eq!(
+            fonts[2].get_font_filename(&template),
+            "roboto-flex-400-normal-latin.woff2"
+        );
+        assert_eq!(normalize_stretch("75% 125%"), "75pct-125pct");
+        assert_eq!(normalize_stretch("semi-condensed"), "semi-condensed");
+    }
+
+    #[test]
+    fn test_multiple_font_sources() {
+        let fonts = parse_fonts(
+            r"/* latin */
+@font-face {
+  font-family: 'Roboto';
+  font-style: normal;
+  font-weight: 400;
+  font-display: swap;
+  src: local('Roboto'), url(https://fonts.gstatic.com/s/roboto/v47/latin.woff2) format('woff2'), url(https://fonts.gstatic.com/s/roboto/v47/latin.woff) format('woff');
+}",
+        );
+        assert_eq!(
+            fonts[0].sources,
+            vec![
+                (
+                    "https://fonts.gstatic.com/s/roboto/v47/latin.woff2".to_string(),
+                    FontFormat::Woff2
+                ),
+                (
+                    "https://fonts.gstatic.com/s/roboto/v47/latin.woff".to_string(),
+                    FontFormat::Woff
+                ),
+            ]
+        );
+        assert_eq!(
+            fonts[0].url,
+            "https://fonts.gstatic.com/s/roboto/v47/latin.woff2"
+        );
+        assert_eq!(fonts[0].format, FontFormat::Woff2);
+    }
+
+    #[test]
+    fn test_new_css_with_base_url() {
+        let font = parse_fonts(
+            r"/* latin */
+@font-face {
+  font-family: 'Roboto';
+  font-style: normal;
+  font-weight: 400;
+  src: url(https://fonts.gstatic.com/s/roboto/v47/latin.woff2) format('woff2');
+}",
+        )
+        .remove(0);
+        let files = [(
+            "roboto-400-normal-latin.woff2".to_string(),
+            FontFormat::Woff2,
+        )];
+        assert!(
+            font.get_new_css("../fonts", &files, None)
+                .contains("src: url(../fonts/roboto-400-normal-latin.woff2) format('woff2');")
+        );
+        assert!(
+            font.get_new_css("https://cdn.example.com/fonts", &files, None)
+                .contains("src: url(https://cdn.example.com/fonts/roboto-400-normal-latin.woff2) format('woff2');")
+        );
+    }
+
+    #[test]
+    fn test_new_css_with_multiple_formats() {
+        let font = parse_fonts(
+            r"/* latin */
+@font-face {
+  font-family: 'Roboto';
+  font-style: normal;
+  font-weight: 400;
+  font-display: swap;
+  src: local('Roboto'), url(https://fonts.gstatic.com/s/roboto/v47/latin.woff) format('woff'), url(https://fonts.gstatic.com/s/roboto/v47/latin.woff2) format('woff2');
+}",
+        )
+        .remove(0);
+        let files = [
+            ("roboto-400-normal-latin.woff".to_string(), FontFormat::Woff),
+            (
+                "roboto-400-normal-latin.woff2".to_string(),
+                FontFormat::Woff2,
+            ),
+        ];
+        assert_eq!(
+            font.get_new_css("/fonts", &files, None),
+            "@font-face {\n  font-family: 'Roboto';\n  font-style: normal;\n  font-weight: 400;\n  font-display: swap;\n  src: local('Roboto'), url(/fonts/roboto-400-normal-latin.woff2) format('woff2'), url(/fonts/roboto-400-normal-latin.woff) format('woff');\n}"
+        );
+    }
+
+    #[test]
+    fn test_new_css_with_font_display() {
+        let files = [(
+            "roboto-400-normal-latin.woff2".to_string(),
+            FontFormat::Woff2,
+        )];
+        let font = parse_fonts(
+            "@font-face {\n  font-family: 'Roboto';\n  font-style: normal;\n  font-weight: 400;\n  font-display: swap;\n  src: url(https://fonts.gstatic.com/s/roboto/v47/latin.woff2) format('woff2');\n}",
+        )
+        .remove(0);
+        assert_eq!(
+            font.get_new_css(".", &files, Some("optional")),
+            "@font-face {\n  font-family: 'Roboto';\n  font-style: normal;\n  font-weight: 400;\n  font-display: optional;\n  src: url(./roboto-400-normal-latin.woff2) format('woff2');\n}"
+        );
+
+        // A font without the descriptor gets one
+        let font = test_font("Roboto");
+        assert_eq!(
+            font.get_new_css(".", &files, Some("block")),
+            "@font-face {\n  font-family: 'Roboto';\n  font-style: normal;\n  font-weight: 400;\n  font-display: block;\n  src: url(./roboto-400-normal-latin.woff2) format('woff2');\n}"
+        );
+    }
+
+    #[test]
+    fn test_new_css_with_windows_prefix() {
+        let files = [(
+            "roboto-400-normal-latin.woff2".to_string(),
+            FontFormat::Woff2,
+        )];
+        let font = test_font("Roboto");
+        for font_prefix in [
+            "..\\static\\fonts\\",
+            "..\\static/fonts",
+            "../static/fonts/",
+        ] {
+            assert!(font.get_new_css(font_prefix, &files, None).contains(
+                "src: url(../static/fonts/roboto-400-normal-latin.woff2) format('woff2');"
+            ));
+        }
+        // An empty prefix still means the root of the site
+        assert!(
+            font.get_new_css("", &files, None)
+                .contains("src: url(/roboto-400-normal-latin.woff2)")
+        );
+    }
+
+    #[test]
+    fn test_new_css_with_local_source() {
+        let files = [(
+            "roboto-400-normal-latin.woff2".to_string(),
+            FontFormat::Woff2,
+        )];
+        let font = test_font("Roboto");
+        assert!(font.rewrite_css("./", &files, None, true).contains(
+            "src: local('Roboto'), url(./roboto-400-normal-latin.woff2) format('woff2');"
+        ));
+        assert_eq!(
+            font.rewrite_css("./", &files, None, false),
+            font.get_new_css("./", &files, None)
+        );
+
+        // A font that already has the local() source doesn't get it twice
+        let font = FontInfo::parse(
+            "@font-face {\n  font-family: 'Roboto';\n  font-style: normal;\n  font-weight: 400;\n  src: local('Roboto'), url(https://fonts.gstatic.com/s/font.woff2) format('woff2');\n}",
+            "latin",
+        )
+        .unwrap();
+        assert!(font.rewrite_css("./", &files, None, true).contains(
+            "src: local('Roboto'), url(./roboto-400-normal-latin.woff2) format('woff2');"
+        ));
+    }
+
+    #[test]
+    fn test_zip_entry_name() {
+        let mut config = Config::default();
+        let font_path = config
+            .output_dir
+            .join("roboto/roboto-400-normal-latin.woff2");
+        assert_eq!(
+            zip_entry_name(&font_path, &config),
+            "roboto/roboto-400-normal-latin.woff2"
+        );
+
+        // The CSS goes at the top, with the font files where the prefix says they are
+        config.output_dir = PathBuf::from("public/fonts");
+        config.css_output_dir = Some(PathBuf::from("public"));
+        let font_path = config.output_dir.join("roboto-400-normal-latin.woff2");
+        assert_eq!(
+            zip_entry_name(&config.css_dir().join("fonts.css"), &config),
+            "fonts.css"
+        );
+        assert_eq!(
+            zip_entry_name(&font_path, &config),
+            "fonts/roboto-400-normal-latin.woff2"
+        );
+        config.css_output_dir = Some(PathBuf::from("css"));
+        config.fonts_prefix_in_css = "./static/fonts/".to_string();
+        assert_eq!(
+            zip_entry_name(&font_path, &config),
+            "static/fonts/roboto-400-normal-latin.woff2"
+        );
+
+        // A prefix that isn't a path inside the archive can't be followed
+        for fonts_prefix in ["/fonts", "../fonts", "https://example.com/fonts"] {
+            config.fonts_prefix_in_css = fonts_prefix.to_string();
+            assert_eq!(
+                zip_entry_name(&font_path, &config),
+                "roboto-400-normal-latin.woff2"
+            );
+        }
+
+        // A font file's path is where it is in the archive
+        let file = DownloadedFile {
+            url: MOCK_FONT_URL.to_string(),
+            format: FontFormat::Woff2,
+            filename: "roboto-400-normal-latin.woff2".to_string(),
+            size: 0,
+            integrity: None,
+            status: FileStatus::Written,
+        };
+        config.fonts_prefix_in_css = "./fonts/".to_string();
+        assert_eq!(
+            file.path(&config),
+            PathBuf::from("public/fonts/roboto-400-normal-latin.woff2")
+        );
+        config.zip = Some(PathBuf::from("fonts.zip"));
+        assert_eq!(
+            file.path(&config),
+            PathBuf::from("fonts.zip/fonts/roboto-400-normal-latin.woff2")
+        );
+    }
+
+    #[test]
+    fn test_normalize_family() {
+        assert_eq!(normalize_family("Open Sans"), "open-sans");
+        assert_eq!(normalize_family("M PLUS 1p"), "m-plus-1p");
+        assert_eq!(normalize_family("Font: The Sequel"), "font-the-sequel");
+        assert_eq!(normalize_family("AC/DC \\ Rock?"), "ac-dc-rock");
+        assert_eq!(normalize_family("<Tag>|\"Quoted\"*"), "tag-quoted");
+        assert_eq!(normalize_family("Trailing Dot."), "trailing-dot");
+        assert_eq!(
+            normalize_family("Zen Kaku Gothic New"),
+            "zen-kaku-gothic-new"
+        );
+        assert_eq!(normalize_family("Ünïcødé"), "ünïcødé");
+        assert_eq!(normalize_family("???"), "font");
+        assert_eq!(normalize_family(""), "font");
+
+        // The filename never has characters that can't be written
+        let font = test_font("Font: The Sequel");
+        assert_eq!(
+            font.get_font_filename(&FilenameTemplate::default()),
+            "font-the-sequel-400-normal-latin.woff2"
+        );
+    }
+
+    #[test]
+    fn test_url_prefix() {
+        assert_eq!(url_prefix("./"), ".");
+        assert_eq!(url_prefix("."), ".");
+        assert_eq!(url_prefix(".//fonts/./"), "./fonts");
+        assert_eq!(url_prefix("/"), "");
+        assert_eq!(url_prefix("//"), "//");
+        assert_eq!(url_prefix("/static//fonts/"), "/static/fonts");
+        assert_eq!(url_prefix("../fonts"), "../fonts");
+        assert_eq!(url_prefix("fonts/./"), "fonts");
+        assert_eq!(url_prefix(""), "");
+        assert_eq!(
+            url_prefix("https://cdn.example.com//fonts/"),
+            "https://cdn.example.com/fonts"
+        );
+        assert_eq!(
+            url_prefix("//cdn.example.com/fonts"),
+            "//cdn.example.com/fonts"
+        );
+    }
+
+    #[test]
+    fn test_new_css_prefixes() {
+        let files = [(
+            "roboto-400-normal-latin.woff2".to_string(),
+            FontFormat::Woff2,
+        )];
+        let font = test_font("Roboto");
+        for (font_prefix, url) in [
+            ("./", "./roboto-400-normal-latin.woff2"),
+            (".//", "./roboto-400-normal-latin.woff2"),
+            ("/", "/roboto-400-normal-latin.woff2"),
+            ("../fonts", "../fonts/roboto-400-normal-latin.woff2"),
+            ("../fonts//", "../fonts/roboto-400-normal-latin.woff2"),
+            ("", "/roboto-400-normal-latin.woff2"),
+        ] {
+            let css = font.get_new_css(font_prefix, &files, None);
+            assert!(
+                css.contains(&format!("src: url({url}) format('woff2');")),
+                "{font_prefix:?}: {css}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_to_font_face_css() {
+        // Oddly formatted CSS, with the descriptors out of order and an unknown descriptor
+        let font = FontInfo::parse(
+            "@font-face {\n    src:   local('Roboto Flex'),url(https://fonts.gstatic.com/s/robotoflex/v26/latin.woff2) format('woff2'), url(https://fonts.gstatic.com/s/robotoflex/v26/latin.ttf) format('truetype');\n    unicode-range: U+0000-00FF, U+0131;\n    font-display: swap;\n    font-feature-settings: \"liga\" 0;\n    font-stretch: 25% 151%;\n    font-weight: 100 1000;\n    font-style: oblique 0deg 10deg;\n    font-family: 'Roboto Flex';\n}",
+            "latin",
+        )
+        .unwrap();
+        let css = font.to_font_face_css();
+        assert_eq!(
+            css,
+            "@font-face {\n  font-family: 'Roboto Flex';\n  font-style: oblique 0deg 10deg;\n  font-weight: 100 1000;\n  font-stretch: 25% 151%;\n  font-display: swap;\n  src: local('Roboto Flex'), url(https://fonts.gstatic.com/s/robotoflex/v26/latin.woff2) format('woff2'), url(https://fonts.gstatic.com/s/robotoflex/v26/latin.ttf) format('truetype');\n  unicode-range: U+0000-00FF, U+0131;\n}"
+        );
+
+        for font in [font, test_font("Roboto")] {
+            let reparsed =
+                FontInfo::parse(&font.to_font_face_css(), &font.writing_system_name).unwrap();
+            assert_eq!(reparsed.family, font.family);
+            assert_eq!(reparsed.style, font.style);
+            assert_eq!(reparsed.weight, font.weight);
+            assert_eq!(reparsed.stretch, font.stretch);
+            assert_eq!(reparsed.display, font.display);
+            assert_eq!(reparsed.unicode_range, font.unicode_range);
+            assert_eq!(reparsed.sources, font.sources);
+            assert_eq!(reparsed.url, font.url);
+            assert_eq!(reparsed.format, font.format);
+            // Reconstructing is stable
+            assert_eq!(reparsed.to_font_face_css(), font.to_font_face_css());
+        }
+    }
+
+    #[test]
+    fn test_new_css_keeps_unknown_descriptors() {
+        let files = [(
+            "roboto-flex-100-900-normal-latin.woff2".to_string(),
+            FontFormat::Woff2,
+        )];
+        let descriptors = "  font-stretch: 25% 151%;\n  font-feature-settings: \"liga\" 0, \"ss01\";\n  font-variation-settings: 'GRAD' 0, 'opsz' 14;\n  size-adjust: 104.5%;\n  ascent-override: 90%;\n  -webkit-font-smoothing: antialiased;\n";
+        let font = parse_fonts(&format!(
+            "/* latin */\n@font-face {{\n  font-family: 'Roboto Flex';\n  font-style: normal;\n  font-weight: 100 900;\n{descriptors}  src: url(https://fonts.gstatic.com/s/robotoflex/v26/latin.woff2) format('woff2');\n  unicode-range: U+0000-00FF;\n}}"
+        ))
+        .remove(0);
+        // Every descriptor that isn't rewritten is kept exactly as it was, in the same place
+        for font_display in [None, Some("swap")] {
+            let font_display_line = font_display
+                .map(|font_display| format!("  font-display: {font_display};\n"))
+                .unwrap_or_default();
+            assert_eq!(
+                font.get_new_css(".", &files, font_display),
+                format!(
+                    "@font-face {{\n  font-family: 'Roboto Flex';\n  font-style: normal;\n  font-weight: 100 900;\n{descriptors}{font_display_line}  src: url(./roboto-flex-100-900-normal-latin.woff2) format('woff2');\n  unicode-range: U+0000-00FF;\n}}"
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn test_integrity_hash() {
+        // The SHA-384 digest of "abc" from FIPS 180-2
+        assert_eq!(
+            integrity_hash(b"abc"),
+            "sha384-ywB1P0WjXou1oD1pmsZQBycsMqsO3tFjGotgWkP/W+2AhgcroefMI1i67KE0yCWn"
+        );
+    }
+
+    #[test]
+    fn test_checksum_file() {
+        assert_eq!(
+            checksum_file(b"abc", Path::new("fonts/roboto-400-normal-latin.woff2")),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad  roboto-400-normal-latin.woff2\n"
+        );
+    }
+
+    #[test]
+    fn test_build_client_with_proxy() {
+        for proxy in ["http://proxy.example.com:8080", "socks5h://127.0.0.1:1080"] {
+            let config = Config {
+                proxy: Some(proxy.to_string()),
+                ..Config::default()
+            };
+            assert!(build_client(&config).is_ok(), "{proxy}");
+        }
+
+        let config = Config {
+            proxy: Some("http://[::1".to_string()),
+            ..Config::default()
+        };
+        let error = build_client(&config).unwrap_err();
+        assert!(matches!(error, DownloadError::InvalidProxy { .. }));
+        assert!(
+            error
+                .to_string()
+                .starts_with("Invalid proxy URL 'http://[::1'")
+        );
+    }
+
+    #[test]
+    fn test_download_error() {
+        let error = DownloadError::BadStatus {
+            url: "https://fonts.googleapis.com/css2?family=Missing".to_string(),
+            status: 400,
+        };
+        assert_eq!(
+            error.to_string(),
+            "Request to 'https://fonts.googleapis.com/css2?family=Missing' failed with status 400."
+        );
+    }
+
+    #[test]
+    fn test_css_request_url() {
+        let url = "https://fonts.googleapis.com/css2?family=Roboto&display=swap";
+        assert_eq!(css_request_url(url, &Config::default()), url);
+        let config = Config {
+            text: Some("Hello, World!".to_string()),
+            ..Config::default()
+        };
+        assert_eq!(
+            css_request_url(url, &config),
+            "https://fonts.googleapis.com/css2?family=Roboto&display=swap&text=Hello%2C+World%21"
+        );
+    }
+
+    #[test]
+    fn test_split_url_label() {
+        assert_eq!(
+            split_url_label("Roboto=https://fonts.googleapis.com/css2?family=Roboto"),
+            (
+                Some("Roboto"),
+                "https://fonts.googleapis.com/css2?family=Roboto"
+            )
+        );
+        for url in [
+            "https://fonts.googleapis.com/css2?family=Roboto",
+            "=https://fonts.googleapis.com/css2?family=Roboto",
+            "..=https://fonts.googleapis.com/css2?family=Roboto",
+        ] {
+            assert_eq!(split_url_label(url).0, None, "{url}");
+        }
+    }
+
+    #[test]
+    fn test_font_format_mime() {
+        assert_eq!(FontFormat::Woff2.to_mime(), "font/woff2");
+        assert_eq!(FontFormat::Woff.to_mime(), "font/woff");
+        assert_eq!(FontFormat::TrueType.to_mime(), "font/ttf");
+        assert_eq!(FontFormat::OpenType.to_mime(), "font/otf");
+        assert_eq!(
+            FontFormat::EmbeddedOpenType.to_mime(),
+            "application/vnd.ms-fontobject"
+        );
+        assert_eq!(FontFormat::Svg.to_mime(), "image/svg+xml");
+        assert_eq!(FontFormat::Unknown.to_mime(), "application/octet-stream");
+    }
+
+    #[test]
+    fn test_font_format_names() {
+        for (name, format, extension) in [
+            ("truetype", FontFormat::TrueType, "ttf"),
+            ("opentype", FontFormat::OpenType, "otf"),
+            ("embedded-opentype", FontFormat::EmbeddedOpenType, "eot"),
+            ("svg", FontFormat::Svg, "svg"),
+            ("woff", FontFormat::Woff, "woff"),
+            ("woff2", FontFormat::Woff2, "woff2"),
+        ] {
+            assert_eq!(FontFormat::from_str(name), format);
+            assert_eq!(FontFormat::from_str(&name.to_uppercase()), format);
+            assert_eq!(format.to_css_format(), name);
+            assert_eq!(format.to_extension(), extension);
+        }
+        assert_eq!(FontFormat::from_str("collection"), FontFormat::Unknown);
+
+        // Every known format in a CSS src is recognised, and sorted in the order browsers should prefer them
+        let sources = parse_sources(
+            "url(a.eot?#iefix) format('embedded-opentype'), url(a.svg#Roboto) format('svg'), url(a.otf) format(\"opentype\"), url(a.woff2) format('woff2')",
+        );
+        let mut formats: Vec<FontFormat> = sources.into_iter().map(|(_, format)| format).collect();
+        assert_eq!(
+            formats,
+            vec![
+                FontFormat::EmbeddedOpenType,
+                FontFormat::Svg,
+                FontFormat::OpenType,
+                FontFormat::Woff2
+            ]
+        );
+        formats.sort_by_key(FontFormat::preference);
+        assert_eq!(
+            formats,
+            vec![
+                FontFormat::Woff2,
+                FontFormat::OpenType,
+                FontFormat::EmbeddedOpenType,
+                FontFormat::Svg
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let rate_limiter = RateLimiter::new(2.0);
+        let start = Instant::now();
+        // The first second's worth of requests go straight away, then they're spaced out
+        assert_eq!(rate_limiter.reserve(start), Duration::ZERO);
+        assert_eq!(rate_limiter.reserve(start), Duration::ZERO);
+        assert_eq!(rate_limiter.reserve(start), Duration::from_millis(500));
+        assert_eq!(rate_limiter.reserve(start), Duration::from_secs(1));
+        assert_eq!(
+            rate_limiter.reserve(start + Duration::from_secs(1)),
+            Duration::from_millis(500)
+        );
+
+        // Less than one request per second still allows a single request straight away
+        let rate_limiter = RateLimiter::new(0.5);
+        assert_eq!(rate_limiter.reserve(start), Duration::ZERO);
+        assert_eq!(rate_limiter.reserve(start), Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_css_decompresses_gzip() {
+        use std::io::{Read, Write};
+
+        let css = "@font-face {\n  font-family: 'Roboto';\n}\n";
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(css.as_bytes()).unwrap();
+        let body = encoder.finish().unwrap();
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!(
+            "http://{}/css2?family=Roboto",
+            listener.local_addr().unwrap()
+        );
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 4096];
+            let length = stream.read(&mut request).unwrap();
+            stream
+                .write_all(
+                    format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: text/css\r\nContent-Encoding: gzip\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                        body.len()
+                    )
+                    .as_bytes(),
+                )
+                .unwrap();
+            stream.write_all(&body).unwrap();
+            String::from_utf8_lossy(&request[..length]).to_lowercase()
+        });
+
+        let config = Config::default();
+        let client = build_client(&config).unwrap();
+        let (response, _) = fetch_css(&client, &url, &config).await.unwrap();
+        assert_eq!(response, css.as_bytes());
+        let request = server.join().unwrap();
+        assert!(request.contains("accept: text/css"), "{request}");
+        assert!(request.contains("accept-encoding: gzip"), "{request}");
+    }
+
+    /// Answers requests with canned bodies, and records which URLs were requested.
+    #[derive(Debug, Clone, Default)]
+    struct MockHttp {
+        responses: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+        content_types: Arc<Mutex<HashMap<String, String>>>,
+        requests: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl MockHttp {
+        fn respond(&self, url: &str, body: &[u8]) {
+            self.responses
+                .lock()
+                .unwrap()
+                .insert(url.to_string(), body.to_vec());
+        }
+
+        fn set_content_type(&self, url: &str, content_type: &str) {
+            self.content_types
+                .lock()
+                .unwrap()
+                .insert(url.to_string(), content_type.to_string());
+        }
+
+        fn take_requests(&self) -> Vec<String> {
+            std::mem::take(&mut self.requests.lock().unwrap())
+        }
+
+        fn client(&self) -> Client {
+            Client {
+                http: Box::new(self.clone()),
+                rate_limiter: None,
+            }
+        }
+    }
+
+    impl Http for MockHttp {
+        fn get<'a>(
+            &'a self,
+            url: &'a str,
+            _accept: &'a str,
+            _user_agent: &'a str,
+            _max_bytes: Option<u64>,
+        ) -> BoxFuture<'a, Result<Body, HttpError>> {
+            self.requests.lock().unwrap().push(url.to_string());
+            let response = self.responses.lock().unwrap().get(url).cloned();
+            let content_type = self.content_types.lock().unwrap().get(url).cloned();
+            Box::pin(async move {
+                let bytes = response.ok_or(HttpError::Status(404))?;
+                Ok(Body {
+                    bytes,
+                    content_type,
+                })
+            })
+        }
+
+        /// The mock's `ETag` is a hash of the body, so changing the response changes it.
+        fn get_if_modified<'a>(
+            &'a self,
+            url: &'a str,
+            accept: &'a str,
+            user_agent: &'a str,
+            max_bytes: Option<u64>,
+            validators: &'a Validators,
+        ) -> BoxFuture<'a, Result<ConditionalBody, HttpError>> {
+            Box::pin(async move {
+                let body = self.get(url, accept, user_agent, max_bytes).await?.bytes;
+                let etag = format!("\"{}\"", short_hash(&String::from_utf8_lossy(&body)));
+                if validators.etag.as_ref() == Some(&etag) {
+                    return Ok(None);
+                }
+                let validators = Validators {
+                    etag: Some(etag),
+                    last_modified: None,
+                };
+                Ok(Some((body, validators)))
+            })
+        }
+
+        fn content_length<'a>(
+            &'a self,
+            url: &'a str,
+            _user_agent: &'a str,
+        ) -> BoxFuture<'a, Result<Option<u64>, HttpError>> {
+            let response = self.responses.lock().unwrap().get(url).cloned();
+            Box::pin(async move {
+                response
+                    .map(|body| Some(body.len() as u64))
+                    .ok_or(HttpError::Status(404))
+            })
+        }
+    }
+
+    /// Download the fonts from one URL, like `download` does for each URL but without the combined files.
+    async fn download_fonts(
+        url: &str,
+        label: Option<&str>,
+        config: &Config,
+        client: &Client,
+        downloaded_files: &mut DownloadedFiles,
+    ) -> Result<Vec<DownloadedFont>, DownloadError> {
+        let (fonts, not_modified) = fetch_fonts(url, config, client).await?;
+        download_parsed_fonts(
+            fonts,
+            not_modified,
+            label,
+            config,
+            client,
+            downloaded_files,
+            &mut Output::Files,
+        )
+        .await
+    }
+
+    const MOCK_CSS_URL: &str = "https://fonts.googleapis.com/css2?family=Roboto";
+    const MOCK_FONT_URL: &str = "https://fonts.gstatic.com/s/roboto/v1/latin.woff2";
+
+    /// An empty temporary directory for a test, which is removed when it's dropped, even if the test fails.
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn path(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn test_dir(name: &str) -> TestDir {
+        let path = std::env::temp_dir().join(format!(
+            "google-fonts-downloader-{name}-{}",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TestDir(path)
+    }
+
+    /// A mock serving a stylesheet with one font, and a config writing to an empty temporary directory.
+    fn mock_download(name: &str) -> (MockHttp, Config, TestDir) {
+        let http = MockHttp::default();
+        http.respond(
+            MOCK_CSS_URL,
+            format!(
+                "/* latin */\n@font-face {{\n  font-family: 'Roboto';\n  font-style: normal;\n  font-weight: 400;\n  src: url({MOCK_FONT_URL}) format('woff2');\n}}\n"
+            )
+            .as_bytes(),
+        );
+        let dir = test_dir(name);
+        let config = Config {
+            urls: vec![MOCK_CSS_URL.to_string()],
+            output_dir: dir.path().to_path_buf(),
+            quiet: true,
+            ..Config::default()
+        };
+        (http, config, dir)
+    }
+
+    /// A mock serving a stylesheet with Roboto in three subsets, and a config only wanting two of them.
+    fn mock_subsets(name: &str) -> (MockHttp, Config, TestDir) {
+        let (http, mut config, dir) = mock_download(name);
+        let mut css = String::new();
+        for subset in ["cyrillic", "latin-ext", "latin"] {
+            let _ = write!(
+                css,
+                "/* {subset} */\n@font-face {{\n  font-family: 'Roboto';\n  font-style: normal;\n  font-weight: 400;\n  src: url(https://fonts.gstatic.com/s/roboto/v1/{subset}.woff2) format('woff2');\n}}\n"
+            );
+        }
+        http.respond(MOCK_CSS_URL, css.as_bytes());
+        config.subsets = vec!["latin".to_string(), "latin-ext".to_string()];
+        (http, config, dir)
+    }
+
+    #[tokio::test]
+    async fn test_font_urls() {
+        let (http, config, _dir) = mock_subsets("font-urls");
+        assert_eq!(
+            font_urls_with(&config, &http.client()).await.unwrap(),
+            vec![
+                "https://fonts.gstatic.com/s/roboto/v1/latin-ext.woff2",
+                "https://fonts.gstatic.com/s/roboto/v1/latin.woff2",
+            ]
+        );
+        // Only the CSS is downloaded
+        assert_eq!(http.take_requests(), vec![MOCK_CSS_URL]);
+    }
+
+    #[tokio::test]
+    async fn test_rewritten_css() {
+        let (http, mut config, _dir) = mock_subsets("rewritten-css");
+        let src = |css: &str| -> Vec<String> {
+            css.lines()
+                .filter(|line| line.starts_with("  src: "))
+                .map(str::to_string)
+                .collect()
+        };
+
+        config.fonts_prefix_in_css = "../fonts".to_string();
+        let css = rewritten_css_with(&config, &http.client()).await.unwrap();
+        assert_eq!(
+            src(&css),
+            vec![
+                "  src: url(../fonts/roboto-400-normal-latin-ext.woff2) format('woff2');",
+                "  src: url(../fonts/roboto-400-normal-latin.woff2) format('woff2');",
+            ]
+        );
+        assert!(!css.contains("cyrillic"));
+
+        config.fonts_prefix_in_css = "https://cdn.example.com/fonts".to_string();
+        let css = rewritten_css_with(&config, &http.client()).await.unwrap();
+        assert_eq!(
+            src(&css),
+            vec![
+                "  src: url(https://cdn.example.com/fonts/roboto-400-normal-latin-ext.woff2) format('woff2');",
+                "  src: url(https://cdn.example.com/fonts/roboto-400-normal-latin.woff2) format('woff2');",
+            ]
+        );
+
+        // Nothing is downloaded or written apart from the CSS
+        assert_eq!(http.take_requests(), vec![MOCK_CSS_URL, MOCK_CSS_URL]);
+        assert_eq!(fs::read_dir(&config.output_dir).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_fonts_without_valid_blocks() {
+        let config = Config {
+            quiet: true,
+            ..Config::default()
+        };
+        let http = MockHttp::default();
+        for css in [
+            "".as_bytes(),
+            b"<!DOCTYPE html><html><body>Not found</body></html>",
+        ] {
+            http.respond(MOCK_CSS_URL, css);
+            let result = fetch_fonts(MOCK_CSS_URL, &config, &http.client()).await;
+            assert!(
+                matches!(result, Err(DownloadError::NoFonts { .. })),
+                "{:?}",
+                String::from_utf8_lossy(css)
+            );
+        }
+
+        // The only block is missing its src
+        http.respond(
+            MOCK_CSS_URL,
+            b"@font-face {\n  font-family: 'Roboto';\n  font-style: normal;\n  font-weight: 400;\n}\n",
+        );
+        let result = fetch_fonts(MOCK_CSS_URL, &config, &http.client()).await;
+        assert!(matches!(
+            result,
+            Err(DownloadError::InvalidFonts { blocks: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn test_urls_failed_message() {
+        let failure = |url: &str| UrlFailure {
+            url: url.to_string(),
+            error: DownloadError::BadStatus {
+                url: url.to_string(),
+                status: 404,
+            },
+        };
+        let error = DownloadError::UrlsFailed {
+            fonts: Vec::new(),
+            failures: vec![
+                failure("https://a.example/css"),
+                failure("https://b.example/css"),
+            ],
+        };
+        assert_eq!(
+            error.to_string(),
+            "2 of the CSS URLs failed: 'https://a.example/css', 'https://b.example/css'."
+        );
+    }
+
+    #[test]
+    fn test_check_url_scheme() {
+        let url = "http://fonts.gstatic.com/s/roboto/v47/latin.woff2";
+        assert_eq!(check_url_scheme(url, false).unwrap(), url);
+        assert_eq!(
+            check_url_scheme(url, true).unwrap(),
+            "https://fonts.gstatic.com/s/roboto/v47/latin.woff2"
+        );
+        assert_eq!(
+            check_url_scheme("HTTP://fonts.gstatic.com/latin.woff2", true).unwrap(),
+            "https://fonts.gstatic.com/latin.woff2"
+        );
+        let url = "https://fonts.googleapis.com/css2?family=Roboto";
+        assert_eq!(check_url_scheme(url, true).unwrap(), url);
+        assert!(matches!(
+            check_url_scheme("ftp://fonts.gstatic.com/latin.woff2", false),
+            Err(DownloadError::UnsupportedScheme { scheme, .. }) if scheme == "ftp"
+        ));
+        // Relative URLs are caught by the host check instead
+        assert_eq!(
+            check_url_scheme("/s/latin.woff2", true).unwrap(),
+            "/s/latin.woff2"
+        );
+    }
+
+    #[test]
+    fn test_check_font_host() {
+        let allowed_hosts = vec![
+            DEFAULT_ALLOWED_HOST.to_string(),
+            "Fonts.Example.com".to_string(),
+        ];
+        assert!(
+            check_font_host(
+                "https://fonts.gstatic.com/s/roboto/v47/latin.woff2",
+                &allowed_hosts
+            )
+            .is_ok()
+        );
+        assert!(
+            check_font_host(
+                "https://fonts.example.com:8443/roboto.woff2",
+                &allowed_hosts
+            )
+            .is_ok()
+        );
+        assert!(matches!(
+            check_font_host("https://fonts.gstatic.com.evil.example/roboto.woff2", &allowed_hosts),
+            Err(DownloadError::HostNotAllowed { host, .. }) if host == "fonts.gstatic.com.evil.example"
+        ));
+        assert!(check_font_host("/s/roboto/v47/latin.woff2", &allowed_hosts).is_err());
+    }
+
+    #[test]
+    fn test_event_json() {
+        let json = |event: &Event| serde_json::to_string(event).unwrap();
+        assert_eq!(
+            json(&Event::Wrote {
+                description: "font file",
+                path: Path::new("fonts/roboto-400-normal-latin.woff2"),
+                bytes: 15744,
+                dry_run: false,
+            }),
+            r#"{"event":"wrote","description":"font file","path":"fonts/roboto-400-normal-latin.woff2","bytes":15744,"dry_run":false}"#
+        );
+        assert_eq!(
+            json(&Event::Skipped {
+                path: Path::new("fonts/roboto-400-normal-latin.woff2"),
+                reason: "unchanged",
+            }),
+            r#"{"event":"skipped","path":"fonts/roboto-400-normal-latin.woff2","reason":"unchanged"}"#
+        );
+        assert_eq!(
+            json(&Event::Debug {
+                message: "Found 2 font entries in the CSS".to_string(),
+            }),
+            r#"{"event":"debug","message":"Found 2 font entries in the CSS"}"#
+        );
+        assert_eq!(
+            json(&Event::Summary {
+                summary: &Summary::default(),
+                dry_run: true,
+            }),
+            r#"{"event":"summary","css_urls":0,"files_written":0,"files_skipped":0,"bytes_downloaded":0,"dry_run":true}"#
+        );
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        assert_eq!(retry_delay(0), Duration::from_secs(1));
+        assert_eq!(retry_delay(3), Duration::from_secs(8));
+        // Lots of retries don't overflow the shift
+        assert_eq!(retry_delay(6), Duration::from_secs(64));
+        assert_eq!(retry_delay(u32::MAX), Duration::from_secs(64));
+    }
+
+    #[test]
+    fn test_write_atomically() {
+        let dir = test_dir("atomic");
+
+        let path = dir.path().join("roboto-400-normal-latin.woff2");
+        write_atomically(&path, b"wOF2 first").unwrap();
+        write_atomically(&path, b"wOF2 second").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"wOF2 second");
+        // Only the finished file is left
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        // A failed write doesn't leave a `.part` file behind either
+        assert!(write_atomically(&dir.path().join("missing/font.woff2"), b"wOF2").is_err());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_write_precompressed() {
+        use std::io::Read;
+
+        let dir = test_dir("precompress");
+        let mut config = Config {
+            precompress: vec![Precompression::Gzip, Precompression::Brotli],
+            quiet: true,
+            ..Config::default()
+        };
+
+        let path = dir.path().join("fonts.css");
+        let gunzip = || {
+            let mut decompressed = String::new();
+            flate2::read::GzDecoder::new(fs::File::open(dir.path().join("fonts.css.gz")).unwrap())
+                .read_to_string(&mut decompressed)
+                .unwrap();
+            decompressed
+        };
+        let css = "@font-face {\n  font-family: 'Roboto';\n}\n".repeat(10);
+        write_css_file(&path, &css, "CSS file", &config, &mut Output::Files).unwrap();
+        assert_eq!(gunzip(), css);
+        let mut decompressed = String::new();
+        brotli::Decompressor::new(
+            fs::File::open(dir.path().join("fonts.css.br")).unwrap(),
+            4096,
+        )
+        .read_to_string(&mut decompressed)
+        .unwrap();
+        assert_eq!(decompressed, css);
+
+        // The compressed copies are kept unless overwriting, like the file itself
+        let new_css = "@font-face {\n  font-family: 'Lato';\n}\n";
+        write_css_file(&path, new_css, "CSS file", &config, &mut Output::Files).unwrap();
+        assert_eq!(gunzip(), css);
+        config.overwrite_css = true;
+        write_css_file(&path, new_css, "CSS file", &config, &mut Output::Files).unwrap();
+        assert_eq!(gunzip(), new_css);
+
+        assert_eq!(Precompression::parse("gzip"), Ok(Precompression::Gzip));
+        assert_eq!(Precompression::parse("br"), Ok(Precompression::Brotli));
+        assert!(Precompression::parse("zstd").is_err());
+    }
+
+    #[test]
+    fn test_ensure_output_dir() {
+        let dir = test_dir("ensure");
+        let config = Config::default();
+
+        let output_dir = dir.path().join("static/fonts");
+        ensure_output_dir(&output_dir, "output directory", &config).unwrap();
+        assert!(output_dir.is_dir());
+        ensure_output_dir(&output_dir, "output directory", &config).unwrap();
+
+        let file_path = dir.path().join("fonts.txt");
+        fs::write(&file_path, "").unwrap();
+        let error = ensure_output_dir(&file_path, "output directory", &config).unwrap_err();
+        assert!(matches!(error, DownloadError::NotADirectory { .. }));
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Can't use '{}' as the output directory, it already exists but isn't a directory.",
+                file_path.display()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_download_fonts_skips_existing_files() {
+        let (http, mut config, _dir) = mock_download("skip");
+        let font_path = config.output_dir.join("roboto-400-normal-latin.woff2");
+        let download = async |config: &Config| {
+            download_fonts(
+                MOCK_CSS_URL,
+                None,
+                config,
+                &http.client(),
+                &mut DownloadedFiles::default(),
+            )
+            .await
+            .unwrap()
+        };
+
+        http.respond(MOCK_FONT_URL, b"wOF2 first");
+        let fonts = download(&config).await;
+        assert_eq!(fonts.len(), 1);
+        assert_eq!(fonts[0].files[0].status, FileStatus::Written);
+        assert_eq!(fonts[0].files[0].path(&config), font_path);
+        assert_eq!(fonts[0].files[0].size, 10);
+        assert_eq!(fs::read(&font_path).unwrap(), b"wOF2 first");
+        assert!(
+            config
+                .output_dir
+                .join("roboto-400-normal-latin.css")
+                .exists()
+        );
+        assert_eq!(http.take_requests(), vec![MOCK_CSS_URL, MOCK_FONT_URL]);
+
+        // An existing file is kept unless overwriting
+        http.respond(MOCK_FONT_URL, b"wOF2 second");
+        let fonts = download(&config).await;
+        assert_eq!(fonts[0].files[0].status, FileStatus::Skipped);
+        assert_eq!(fs::read(&font_path).unwrap(), b"wOF2 first");
+
+        // Overwriting the CSS keeps the font files, and overwriting the font files keeps the CSS
+        let css_path = config.output_dir.join("roboto-400-normal-latin.css");
+        fs::write(&css_path, "").unwrap();
+        config.overwrite_css = true;
+        let fonts = download(&config).await;
+        assert_eq!(fonts[0].files[0].status, FileStatus::Skipped);
+        assert_eq!(fs::read(&font_path).unwrap(), b"wOF2 first");
+        assert_ne!(fs::read_to_string(&css_path).unwrap(), "");
+
+        fs::write(&css_path, "").unwrap();
+        config.overwrite_css = false;
+        config.overwrite_fonts = true;
+        let fonts = download(&config).await;
+        assert_eq!(fonts[0].files[0].status, FileStatus::Updated);
+        assert_eq!(fs::read(&font_path).unwrap(), b"wOF2 second");
+        assert_eq!(fs::read_to_string(&css_path).unwrap(), "");
+
+        // Updating only overwrites a font file that has changed
+        config.overwrite_fonts = false;
+        config.update = true;
+        let fonts = download(&config).await;
+        assert_eq!(fonts[0].files[0].status, FileStatus::Skipped);
+        http.respond(MOCK_FONT_URL, b"wOF2 third");
+        let fonts = download(&config).await;
+        assert_eq!(fonts[0].files[0].status, FileStatus::Updated);
+        assert_eq!(fs::read(&font_path).unwrap(), b"wOF2 third");
+    }
+
+    #[tokio::test]
+    async fn test_download_fonts_updates_changed_files() {
+        let (http, mut config, _dir) = mock_download("update");
+        config.update = true;
+        config.sri = true;
+        let font_path = config.output_dir.join("roboto-400-normal-latin.woff2");
+        let download = async |config: &Config| {
+            download_fonts(
+                MOCK_CSS_URL,
+                None,
+                config,
+                &http.client(),
+                &mut DownloadedFiles::default(),
+            )
+            .await
+            .unwrap()
+        };
+
+        http.respond(MOCK_FONT_URL, b"wOF2 first");
+        let fonts = download(&config).await;
+        assert_eq!(fonts[0].files[0].status, FileStatus::Written);
+        let first_integrity = integrity_hash(b"wOF2 first");
+        assert_eq!(fonts[0].files[0].integrity, Some(first_integrity.clone()));
+        let modified = fs::metadata(&font_path).unwrap().modified().unwrap();
+
+        // The same bytes leave the file alone, with the hash that was compared still in the manifest
+        let fonts = download(&config).await;
+        assert_eq!(fonts[0].files[0].status, FileStatus::Skipped);
+        assert_eq!(fonts[0].files[0].integrity, Some(first_integrity));
+        assert_eq!(
+            fs::metadata(&font_path).unwrap().modified().unwrap(),
+            modified
+        );
+
+        // Different bytes are written over the old file
+        http.respond(MOCK_FONT_URL, b"wOF2 second");
+        let fonts = download(&config).await;
+        assert_eq!(fonts[0].files[0].status, FileStatus::Updated);
+        assert_eq!(
+            fonts[0].files[0].integrity,
+            Some(integrity_hash(b"wOF2 second"))
+        );
+        assert_eq!(fs::read(&font_path).unwrap(), b"wOF2 second");
+    }
+
+    #[tokio::test]
+    async fn test_download_fonts_offline() {
+        let (http, mut config, _dir) = mock_download("offline");
+        let ext_font_url = "https://fonts.gstatic.com/s/roboto/v1/latin-ext.woff2";
+        http.respond(
+            MOCK_CSS_URL,
+            format!(
+                "/* latin-ext */\n@font-face {{\n  font-family: 'Roboto';\n  font-style: normal;\n  font-weight: 400;\n  src: url({ext_font_url}) format('woff2');\n}}\n/* latin */\n@font-face {{\n  font-family: 'Roboto';\n  font-style: normal;\n  font-weight: 400;\n  src: url({MOCK_FONT_URL}) format('woff2');\n}}\n"
+            )
+            .as_bytes(),
+        );
+        http.respond(ext_font_url, b"wOF2 latin-ext");
+        http.respond(MOCK_FONT_URL, b"wOF2 latin");
+        config.cache_dir = Some(config.output_dir.join("cache"));
+        let fonts = download_fonts(
+            MOCK_CSS_URL,
+            None,
+            &config,
+            &http.client(),
+            &mut DownloadedFiles::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(fonts.len(), 2);
+
+        // Offline, the CSS comes from the cache, and only the font file that's gone is downloaded again
+        let offline_http = MockHttp::default();
+        offline_http.respond(MOCK_FONT_URL, b"wOF2 latin");
+        config.offline = true;
+        fs::remove_file(config.output_dir.join("roboto-400-normal-latin.woff2")).unwrap();
+        let fonts = download_fonts(
+            MOCK_CSS_URL,
+            None,
+            &config,
+            &offline_http.client(),
+            &mut DownloadedFiles::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(fonts.len(), 2);
+        assert_eq!(offline_http.take_requests(), vec![MOCK_FONT_URL]);
+        assert_eq!(
+            fs::read(config.output_dir.join("roboto-400-normal-latin.woff2")).unwrap(),
+            b"wOF2 latin"
+        );
+
+        // CSS that was never downloaded can't be used offline
+        let result = download_fonts(
+            "https://fonts.googleapis.com/css2?family=Lato",
+            None,
+            &config,
+            &offline_http.client(),
+            &mut DownloadedFiles::default(),
+        )
+        .await;
+        assert!(matches!(result, Err(DownloadError::NotCached { .. })));
+        assert!(offline_http.take_requests().is_empty());
+
+        config.cache_dir = None;
+        let result = download_fonts(
+            MOCK_CSS_URL,
+            None,
+            &config,
+            &offline_http.client(),
+            &mut DownloadedFiles::default(),
+        )
+        .await;
+        assert!(matches!(result, Err(DownloadError::NoCacheDir)));
+    }
+
+    #[tokio::test]
+    async fn test_download_fonts_without_css_or_fonts() {
+        let (http, mut config, _dir) = mock_download("no-css");
+        http.respond(MOCK_FONT_URL, b"wOF2 font");
+        let download = async |config: &Config| {
+            download_fonts(
+                MOCK_CSS_URL,
+                None,
+                config,
+                &http.client(),
+                &mut DownloadedFiles::default(),
+            )
+            .await
+            .unwrap()
+        };
+        let file_names = |config: &Config| {
+            let mut names: Vec<String> = fs::read_dir(&config.output_dir)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+                .collect();
+            names.sort();
+            names
+        };
+
+        // Without CSS, only the font files are written
+        config.no_css = true;
+        let fonts = download(&config).await;
+        assert_eq!(fonts[0].files[0].status, FileStatus::Written);
+        assert_eq!(file_names(&config), vec!["roboto-400-normal-latin.woff2"]);
+        assert_eq!(http.take_requests(), vec![MOCK_CSS_URL, MOCK_FONT_URL]);
+
+        // Only the CSS is written, pointing at where the font file would be, without asking for the font file
+        fs::remove_file(config.output_dir.join("roboto-400-normal-latin.woff2")).unwrap();
+        config.no_css = false;
+        config.css_only = true;
+        let fonts = download(&config).await;
+        assert_eq!(fonts[0].files[0].status, FileStatus::Skipped);
+        assert_eq!(fonts[0].files[0].size, 0);
+        assert_eq!(file_names(&config), vec!["roboto-400-normal-latin.css"]);
+        assert!(
+            fs::read_to_string(config.output_dir.join("roboto-400-normal-latin.css"))
+                .unwrap()
+                .contains("src: url(./roboto-400-normal-latin.woff2) format('woff2');")
+        );
+        assert_eq!(http.take_requests(), vec![MOCK_CSS_URL]);
+    }
+
+    #[tokio::test]
+    async fn test_download_fonts_with_labels() {
+        let (http, config, _dir) = mock_download("labels");
+        http.respond(MOCK_FONT_URL, b"wOF2 font");
+        let mut downloaded_files = DownloadedFiles::default();
+        for label in ["Body", "Headings"] {
+            let fonts = download_fonts(
+                MOCK_CSS_URL,
+                Some(label),
+                &config,
+                &http.client(),
+                &mut downloaded_files,
+            )
+            .await
+            .unwrap();
+            assert_eq!(fonts.len(), 1, "{label}");
+            assert_eq!(
+                fonts[0].files[0].filename,
+                format!("{label}/roboto-400-normal-latin.woff2")
+            );
+            assert!(
+                config
+                    .output_dir
+                    .join(label)
+                    .join("roboto-400-normal-latin.woff2")
+                    .exists()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_download_fonts_if_modified_since() {
+        let (http, mut config, _dir) = mock_download("conditional");
+        config.cache_dir = Some(config.output_dir.join("cache"));
+        config.if_modified_since = true;
+        let font_path = config.output_dir.join("roboto-400-normal-latin.woff2");
+        let download = async |config: &Config| {
+            download_fonts(
+                MOCK_CSS_URL,
+                None,
+                config,
+                &http.client(),
+                &mut DownloadedFiles::default(),
+            )
+            .await
+            .unwrap()
+        };
+
+        http.respond(MOCK_FONT_URL, b"wOF2 first");
+        let fonts = download(&config).await;
+        assert_eq!(fonts[0].files[0].status, FileStatus::Written);
+        assert_eq!(http.take_requests(), vec![MOCK_CSS_URL, MOCK_FONT_URL]);
+
+        // The CSS hasn't changed, so the font file isn't downloaded again
+        let fonts = download(&config).await;
+        assert_eq!(fonts[0].files[0].status, FileStatus::Skipped);
+        assert_eq!(fonts[0].files[0].size, 10);
+        assert_eq!(http.take_requests(), vec![MOCK_CSS_URL]);
+
+        // A missing font file is downloaded again, even though the CSS hasn't changed
+        fs::remove_file(&font_path).unwrap();
+        let fonts = download(&config).await;
+        assert_eq!(fonts[0].files[0].status, FileStatus::Written);
+        assert_eq!(http.take_requests(), vec![MOCK_CSS_URL, MOCK_FONT_URL]);
+
+        // Changed CSS downloads everything again
+        http.respond(
+            MOCK_CSS_URL,
+            format!(
+                "/* latin */\n@font-face {{\n  font-family: 'Roboto';\n  font-style: normal;\n  font-weight: 700;\n  src: url({MOCK_FONT_URL}) format('woff2');\n}}\n"
+            )
+            .as_bytes(),
+        );
+        let fonts = download(&config).await;
+        assert_eq!(fonts[0].font.weight, "700");
+        assert_eq!(http.take_requests(), vec![MOCK_CSS_URL, MOCK_FONT_URL]);
+    }
+
+    #[tokio::test]
+    async fn test_download_fonts_rejects_bad_responses() {
+        let (http, config, _dir) = mock_download("reject");
+
+        // An error page served instead of a font isn't written, and neither is CSS pointing at it
+        http.respond(MOCK_FONT_URL, b"<!DOCTYPE html>");
+        http.set_content_type(MOCK_FONT_URL, "text/html; charset=utf-8");
+        let mut downloaded_files = DownloadedFiles::default();
+        let fonts = download_fonts(
+            MOCK_CSS_URL,
+            None,
+            &config,
+            &http.client(),
+            &mut downloaded_files,
+        )
+        .await
+        .unwrap();
+        assert!(fonts.is_empty());
+        assert_eq!(downloaded_files.rejected, vec![MOCK_FONT_URL]);
+        assert_eq!(fs::read_dir(&config.output_dir).unwrap().count(), 0);
+
+        // A missing stylesheet fails straight away, without retrying
+        http.take_requests();
+        let missing_url = "https://fonts.googleapis.com/css2?family=Missing";
+        let result = download_fonts(
+            missing_url,
+            None,
+            &config,
+            &http.client(),
+            &mut DownloadedFiles::default(),
+        )
+        .await;
+        assert!(matches!(
+            result,
+            Err(DownloadError::BadStatus { status: 404, .. })
+        ));
+        assert_eq!(http.take_requests(), vec![missing_url]);
+    }
+
+    #[test]
+    fn test_saved_format() {
+        let config = Config::default();
+        assert_eq!(saved_format(&FontFormat::Woff2, &config), FontFormat::Woff2);
+        let config = Config {
+            decompress_to_ttf: true,
+            ..Config::default()
+        };
+        assert_eq!(
+            saved_format(&FontFormat::Woff2, &config),
+            FontFormat::TrueType
+        );
+        assert_eq!(saved_format(&FontFormat::Woff, &config), FontFormat::Woff);
+        assert_eq!(
+            saved_format(&FontFormat::TrueType, &config),
+            FontFormat::TrueType
+        );
+    }
+
+    #[test]
+    fn test_is_font_content_type() {
+        assert!(is_font_content_type("font/woff2"));
+        assert!(is_font_content_type("Font/TTF"));
+        assert!(is_font_content_type("application/font-woff"));
+        assert!(is_font_content_type("application/x-font-ttf"));
+        assert!(is_font_content_type("application/octet-stream"));
+        assert!(is_font_content_type("application/vnd.ms-fontobject"));
+        assert!(!is_font_content_type("text/html; charset=utf-8"));
+        assert!(!is_font_content_type("text/css"));
+        assert!(!is_font_content_type(""));
+    }
+
+    #[test]
+    fn test_decode_css() {
+        assert_eq!(
+            decode_css(b"@font-face {}"),
+            (Cow::Borrowed("@font-face {}"), true)
+        );
+        assert_eq!(
+            decode_css(b"\xEF\xBB\xBF@font-face {}"),
+            (Cow::Borrowed("@font-face {}"), true)
+        );
+        let (css, is_valid_utf8) = decode_css(b"/* lat\xFFin */ @font-face {}");
+        assert_eq!(css, "/* lat\u{FFFD}in */ @font-face {}");
+        assert!(!is_valid_utf8);
+    }
+
+    #[test]
+    fn test_check_downloaded_format() {
+        let config = Config {
+            quiet: true,
+            ..Config::default()
+        };
+        let check = |format: FontFormat, bytes: &[u8]| {
+            let font_file = Body {
+                bytes: bytes.to_vec(),
+                content_type: None,
+            };
+            check_downloaded_format("https://example.com/font", &format, &font_file, &config)
+        };
+        // The CSS's name for a TrueType or OpenType file is kept
+        assert_eq!(
+            check(FontFormat::TrueType, b"OTTO\x00\x0b"),
+            Some(FontFormat::TrueType)
+        );
+        assert_eq!(
+            check(FontFormat::OpenType, b"\x00\x01\x00\x00"),
+            Some(FontFormat::OpenType)
+        );
+        // An unknown format is detected, so the file gets an extension
+        assert_eq!(
+            check(FontFormat::Unknown, b"OTTO\x00\x0b"),
+            Some(FontFormat::OpenType)
+        );
+        assert_eq!(
+            check(FontFormat::Unknown, b"<svg><font>"),
+            Some(FontFormat::Svg)
+        );
+        assert_eq!(check(FontFormat::Unknown, b"<!DOCTYPE html>"), None);
+        assert_eq!(check(FontFormat::Woff2, b"<svg><font>"), None);
+        // An XML error page or an SVG image isn't saved as a font
+        assert_eq!(
+            check(
+                FontFormat::Unknown,
+                b"<?xml version=\"1.0\" encoding=\"UTF-8\"?><Error><Code>NoSuchKey</Code></Error>"
+            ),
+            None
+        );
+        assert_eq!(
+            check(FontFormat::Unknown, b"<svg><path d=\"M0 0h1v1z\"/></svg>"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_detect_font_format() {
+        assert_eq!(
+            detect_font_format(b"wOF2\x00\x01\x00\x00"),
+            FontFormat::Woff2
+        );
+        assert_eq!(
+            detect_font_format(b"wOFF\x00\x01\x00\x00"),
+            FontFormat::Woff
+        );
+        assert_eq!(
+            detect_font_format(b"\x00\x01\x00\x00\x00\x0f"),
+            FontFormat::TrueType
+        );
+        assert_eq!(detect_font_format(b"OTTO\x00\x0b"), FontFormat::OpenType);
+        let mut eot = vec![0; 36];
+        eot[34..].copy_from_slice(b"LP");
+        // Without a version it could be anything
+        assert_eq!(detect_font_format(&eot), FontFormat::Unknown);
+        eot[8..12].copy_from_slice(b"\x01\x00\x02\x00");
+        assert_eq!(detect_font_format(&eot), FontFormat::EmbeddedOpenType);
+        assert_eq!(
+            detect_font_format(b"\n<?xml version=\"1.0\"?><svg><defs><font>"),
+            FontFormat::Svg
+        );
+        assert_eq!(detect_font_format(b"<svg><font>"), FontFormat::Svg);
+        assert_eq!(
+            detect_font_format(
+                b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<Error><Code>AccessDenied</Code></Error>"
+            ),
+            FontFormat::Unknown
+        );
+        assert_eq!(
+            detect_font_format(
+                b"<svg xmlns=\"http://www.w3.org/2000/svg\"><circle r=\"1\"/></svg>"
+            ),
+            FontFormat::Unknown
+        );
+        assert_eq!(
+            detect_font_format(b"<?xml ?><font><svg>"),
+            FontFormat::Unknown
+        );
+        assert_eq!(detect_font_format(b"<!DOCTYPE html>"), FontFormat::Unknown);
+        assert_eq!(detect_font_format(b"wOF"), FontFormat::Unknown);
+    }
+
+    #[test]
+    fn test_filter_subsets() {
+        let fonts = parse_fonts(
+            r"/* cyrillic */
+@font-face {
+  font-family: 'Roboto';
+  font-style: normal;
+  font-weight: 400;
+  src: url(https://fonts.gstatic.com/s/roboto/v47/cyrillic.woff2) format('woff2');
+}
+/* latin-ext */
+@font-face {
+  font-family: 'Roboto';
+  font-style: normal;
+  font-weight: 400;
+  src: url(https://fonts.gstatic.com/s/roboto/v47/latin-ext.woff2) format('woff2');
+}
+/* latin */
+@font-face {
+  font-family: 'Roboto';
+  font-style: normal;
+  font-weight: 400;
+  src: url(https://fonts.gstatic.com/s/roboto/v47/latin.woff2) format('woff2');
+}",
+        );
+        let subsets = vec!["latin".to_string(), "latin-ext".to_string()];
+        let fonts = filter_subsets(fonts, &subsets).unwrap();
+        let names: Vec<&str> = fonts
+            .iter()
+            .map(|font| font.writing_system_name.as_str())
+            .collect();
+        assert_eq!(names, vec!["latin-ext", "latin"]);
+
+        // A glob matches every subset it covers
+        let fonts = filter_subsets(fonts, &["lat?n*".to_string()]).unwrap();
+        assert_eq!(fonts.len(), 2);
+
+        let error = filter_subsets(fonts, &["greek".to_string()]).unwrap_err();
+        assert_eq!(error, "the available subsets are latin-ext, latin.");
+    }
+
+    #[test]
+    fn test_matches_glob() {
+        assert!(matches_glob("latin", "latin"));
+        assert!(!matches_glob("latin", "latin-ext"));
+        assert!(matches_glob("latin*", "latin"));
+        assert!(matches_glob("latin*", "latin-ext"));
+        assert!(matches_glob("*-ext", "cyrillic-ext"));
+        assert!(!matches_glob("*-ext", "cyrillic"));
+        assert!(matches_glob("*", ""));
+        assert!(matches_glob("c*l*c", "cyrillic"));
+        assert!(matches_glob("gree?", "greek"));
+        assert!(!matches_glob("gree?", "gree"));
+        assert!(!matches_glob("", "latin"));
+    }
+
+    #[test]
+    fn test_summary() {
+        let font_file = |size, status| DownloadedFile {
+            url: String::new(),
+            format: FontFormat::Woff2,
+            filename: String::new(),
+            size,
+            integrity: None,
+            status,
+        };
+        let fonts = vec![DownloadedFont {
+            font: test_font("Roboto"),
+            files: vec![
+                font_file(1000, FileStatus::Written),
+                font_file(24, FileStatus::Skipped),
+            ],
+        }];
+        let summary = Summary::new(1, &fonts);
+        assert_eq!(
+            summary,
+            Summary {
+                css_urls: 1,
+                files_written: 1,
+                files_skipped: 1,
+                bytes_downloaded: 1024,
+            }
+        );
+        assert_eq!(
+            summary.to_string(),
+            "Processed 1 CSS URLs: 1 font files written, 1 skipped, 1.00 KiB downloaded."
+        );
+    }
+
+    #[test]
+    fn test_filter_families() {
+        let fonts = vec![test_font("Roboto"), test_font("Open Sans")];
+        let families = vec!["open sans".to_string()];
+        let filtered_fonts = filter_families(fonts, &families);
+        assert_eq!(filtered_fonts.len(), 1);
+        assert_eq!(filtered_fonts[0].family, "Open Sans");
+
+        assert_eq!(filter_families(vec![test_font("Roboto")], &[]).len(), 1);
+    }
+
+    #[test]
+    fn test_preferred_source() {
+        let font = FontInfo::parse(
+            "@font-face {\n  font-family: 'Roboto';\n  font-style: normal;\n  font-weight: 400;\n  src: url(https://fonts.gstatic.com/s/roboto.ttf) format('truetype'), url(https://fonts.gstatic.com/s/roboto.woff) format('woff'), url(https://fonts.gstatic.com/s/roboto.woff2) format('woff2');\n}",
+            "latin",
+        )
+        .unwrap();
+        let source = |prefer_formats: &[FontFormat]| preferred_source(&font, prefer_formats).0;
+        assert_eq!(
+            source(&Config::default().prefer_formats),
+            "https://fonts.gstatic.com/s/roboto.woff2"
+        );
+        assert_eq!(
+            source(&[FontFormat::Woff, FontFormat::Woff2]),
+            "https://fonts.gstatic.com/s/roboto.woff"
+        );
+        // With none of the preferred formats, the first source is used
+        assert_eq!(source(&[]), "https://fonts.gstatic.com/s/roboto.ttf");
+    }
+
+    #[test]
+    fn test_filter_weights() {
+        let font = |weight: &str| {
+            let mut font = test_font("Roboto");
+            font.weight = weight.to_string();
+            font
+        };
+        let fonts = vec![font("300"), font("400"), font("bold"), font("100 900")];
+        let (fonts, missing_weights) = filter_weights(fonts, &[400, 700, 950]);
+        let weights: Vec<&str> = fonts.iter().map(|font| font.weight.as_str()).collect();
+        assert_eq!(weights, vec!["400", "bold", "100 900"]);
+        assert_eq!(missing_weights, vec![950]);
+
+        let (fonts, missing_weights) = filter_weights(vec![font("300")], &[]);
+        assert_eq!(fonts.len(), 1);
+        assert!(missing_weights.is_empty());
+    }
+
+    #[test]
+    fn test_manifest_formats() {
+        let file = DownloadedFile {
+            url: "https://fonts.gstatic.com/s/robotoflex/v26/latin.woff2".to_string(),
+            format: FontFormat::Woff2,
+            filename: "roboto-flex-100-1000-normal-latin.woff2".to_string(),
+            size: 1234,
+            integrity: Some("sha384-abc".to_string()),
+            status: FileStatus::Written,
+        };
+        let font = FontInfo::parse(
+            "font-family: 'Roboto Flex, \"Variable\"'; font-style: normal; font-weight: 100 1000; font-stretch: 25% 151%; src: url(https://fonts.gstatic.com/s/robotoflex/v26/latin.woff2);",
+            "latin",
+        )
+        .unwrap();
+        let entries = vec![
+            ManifestEntry::new(&font, &file),
+            ManifestEntry::new(
+                &test_font("Roboto"),
+                &DownloadedFile {
+                    integrity: None,
+                    status: FileStatus::Updated,
+                    ..file
+                },
+            ),
+        ];
+        assert_eq!(
+            manifest_csv(&entries),
+            r#"family,style,weight,stretch,writing_system,url,filename,format,bytes,status,integrity
+"Roboto Flex, ""Variable""",normal,100 1000,25% 151%,latin,https://fonts.gstatic.com/s/robotoflex/v26/latin.woff2,roboto-flex-100-1000-normal-latin.woff2,woff2,1234,written,sha384-abc
+Roboto,normal,400,,latin,https://fonts.gstatic.com/s/robotoflex/v26/latin.woff2,roboto-flex-100-1000-normal-latin.woff2,woff2,1234,updated,
+"#
+        );
+
+        let manifest = Manifest {
+            summary: Summary::default(),
+            fonts: entries,
+        };
+        let manifest = toml::to_string(&manifest).unwrap();
+        assert!(manifest.starts_with("[summary]\ncss_urls = 0\n"));
+        assert_eq!(manifest.matches("[[fonts]]").count(), 2);
+        assert!(manifest.contains("stretch = \"25% 151%\"\n"));
+    }
+
+    #[test]
+    fn test_preload_html() {
+        let font_file = |filename: &str, integrity: Option<&str>| DownloadedFile {
+            url: format!("https://fonts.gstatic.com/s/{filename}"),
+            format: FontFormat::Woff2,
+            filename: filename.to_string(),
+            size: 0,
+            integrity: integrity.map(str::to_string),
+            status: FileStatus::Written,
+        };
+        let fonts = vec![
+            DownloadedFont {
+                font: test_font("Roboto"),
+                files: vec![font_file(
+                    "roboto-400-normal-latin.woff2",
+                    Some("sha384-abc"),
+                )],
+            },
+            DownloadedFont {
+                font: test_font("Roboto"),
+                files: vec![font_file("roboto-700-normal-latin.woff2", None)],
+            },
+        ];
+        assert_eq!(
+            preload_html(&fonts, "/fonts"),
+            "<!-- Roboto -->
+<link rel=\"preload\" as=\"font\" type=\"font/woff2\" href=\"/fonts/roboto-400-normal-latin.woff2\" integrity=\"sha384-abc\" crossorigin>
+<link rel=\"preload\" as=\"font\" type=\"font/woff2\" href=\"/fonts/roboto-700-normal-latin.woff2\" crossorigin>
+"
+        );
+    }
+
+    #[test]
+    fn test_parse_unicode_range() {
+        assert_eq!(
+            parse_unicode_range("U+0000-00FF, U+0131, u+4??").unwrap(),
+            vec![0..=0xFF, 0x131..=0x131, 0x400..=0x4FF]
+        );
+        assert!(parse_unicode_range("0000-00FF").is_err());
+        assert!(parse_unicode_range("U+00FF-0000").is_err());
+        assert!(parse_unicode_range("U+110000").is_err());
+        assert!(parse_unicode_range("U+00GG").is_err());
+    }
+
+    #[test]
+    fn test_filter_codepoints() {
+        let css = "/* cyrillic */\n@font-face {\n  font-family: 'Roboto';\n  font-style: normal;\n  font-weight: 400;\n  src: url(https://fonts.gstatic.com/s/roboto/v47/cyrillic.woff2) format('woff2');\n  unicode-range: U+0301, U+0400-045F, U+0490-0491, U+04B0-04B1, U+2116;\n}\n/* latin */\n@font-face {\n  font-family: 'Roboto';\n  font-style: normal;\n  font-weight: 400;\n  src: url(https://fonts.gstatic.com/s/roboto/v47/latin.woff2) format('woff2');\n  unicode-range: U+0000-00FF, U+0131, U+2000-206F;\n}\n/* text */\n@font-face {\n  font-family: 'Roboto';\n  font-style: normal;\n  font-weight: 400;\n  src: url(https://fonts.gstatic.com/s/roboto/v47/text.woff2) format('woff2');\n}";
+        let writing_systems = |codepoints: &[RangeInclusive<u32>]| {
+            filter_codepoints(parse_fonts(css), codepoints)
+                .into_iter()
+                .map(|font| font.writing_system_name)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(writing_systems(&[]), ["cyrillic", "latin", "text"]);
+        assert_eq!(writing_systems(&[0x41..=0x5A]), ["latin", "text"]);
+        assert_eq!(writing_systems(&[0x2116..=0x2116]), ["cyrillic", "text"]);
+        assert_eq!(
+            writing_systems(&[0x2050..=0x2200]),
+            ["cyrillic", "latin", "text"]
+        );
+        assert_eq!(writing_systems(&[0x3000..=0x30FF]), ["text"]);
+    }
+
+    #[test]
+    fn test_compare_fonts() {
+        let font = |family: &str, style: &str, weight: &str, writing_system_name: &str| {
+            FontInfo::parse(
+                &format!(
+                    "font-family: '{family}'; font-style: {style}; font-weight: {weight}; src: url(a.woff2);"
+                ),
+                writing_system_name,
+            )
+            .unwrap()
+        };
+        let mut fonts = [
+            font("Roboto", "normal", "1000", "latin"),
+            font("Roboto", "normal", "400", "latin"),
+            font("Open Sans", "normal", "400", "latin"),
+            font("Roboto", "italic", "400", "latin"),
+            font("Roboto", "normal", "400", "cyrillic"),
+            font("Roboto", "normal", "100 900", "latin"),
+        ];
+        fonts.sort_by(compare_fonts);
+        let order: Vec<String> = fonts
+            .iter()
+            .map(|font| {
+                format!(
+                    "{} {} {} {}",
+                    font.family, font.weight, font.style, font.writing_system_name
+                )
+            })
+            .collect();
+        assert_eq!(
+            order,
+            vec![
+                "Open Sans 400 normal latin",
+                "Roboto 100 900 normal latin",
+                "Roboto 400 italic latin",
+                // The subsets are left in the order they were in
+                "Roboto 400 normal latin",
+                "Roboto 400 normal cyrillic",
+                "Roboto 1000 normal latin",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_preview_html() {
+        let downloaded_font = |css: &str| DownloadedFont {
+            font: FontInfo::parse(css, "latin").unwrap(),
+            files: Vec::new(),
+        };
+        let fonts = vec![
+            downloaded_font(
+                "font-family: 'Roboto Flex'; font-style: normal; font-weight: 100 1000; font-stretch: 25% 151%; src: url(a.woff2);",
+            ),
+            downloaded_font(
+                "font-family: 'Roboto Flex'; font-style: normal; font-weight: 100 1000; font-stretch: 25% 151%; src: url(b.woff2);",
+            ),
+            downloaded_font(
+                "font-family: 'Tom & Jerry'; font-style: italic; font-weight: 700; src: url(c.woff2);",
+            ),
+        ];
+        assert_eq!(
+            preview_html(&fonts, &["file:///fonts/fonts.css".to_string()]),
+            "<!DOCTYPE html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<title>Font preview</title>
+<link rel=\"stylesheet\" href=\"file:///fonts/fonts.css\">
+</head>
+<body>
+<h2>Roboto Flex 100 1000 normal 25% 151%</h2>
+<p style=\"font-family: 'Roboto Flex'; font-style: normal; font-weight: 100; font-stretch: 25%\">The quick brown fox jumps over the lazy dog 0123456789</p>
+<h2>Tom &amp; Jerry 700 italic</h2>
+<p style=\"font-family: 'Tom &amp; Jerry'; font-style: italic; font-weight: 700\">The quick brown fox jumps over the lazy dog 0123456789</p>
+</body>
+</html>
+"
+        );
+    }
+
+    #[test]
+    fn test_scss_vars() {
+        let font_file = |filename: &str, format| DownloadedFile {
+            url: format!("https://fonts.gstatic.com/s/{filename}"),
+            format,
+            filename: filename.to_string(),
+            size: 0,
+            integrity: None,
+            status: FileStatus::Written,
+        };
+        let fonts = vec![
+            DownloadedFont {
+                font: test_font("Roboto"),
+                files: vec![
+                    font_file("roboto/roboto-400-normal-latin.woff2", FontFormat::Woff2),
+                    font_file("roboto/roboto-400-normal-latin.woff", FontFormat::Woff),
+                ],
+            },
+            DownloadedFont {
+                font: test_font("Open Sans"),
+                files: vec![font_file(
+                    "open-sans/Open Sans 400.woff2",
+                    FontFormat::Woff2,
+                )],
+            },
+        ];
+        assert_eq!(
+            scss_vars(&fonts, "."),
+            r#"// Roboto
+$font-roboto-roboto-400-normal-latin: "./roboto/roboto-400-normal-latin.woff2";
+$font-roboto-roboto-400-normal-latin-woff: "./roboto/roboto-400-normal-latin.woff";
+// Open Sans
+$font-open-sans-open-sans-400: "./open-sans/Open Sans 400.woff2";
+"#
+        );
+    }
+}
@@ -1,263 +1,159 @@
-use clap::{command, value_parser, Arg, ArgAction};
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{Arg, ArgAction, ArgMatches, Command, command, value_parser};
+use clap_complete::Shell;
+use google_fonts_downloader::{
+    Config, CssGrouping, DEFAULT_ALLOWED_HOST, DownloadError, Event, FilenameTemplate, FontFormat,
+    FontInfo, HashAlgorithm, HashEncoding, ManifestFormat, OutputLayout, Precompression, Summary,
+    VerifiedFile, VerifyStatus, download, font_urls, list, parse_unicode_range, rewritten_css,
+    verify,
+};
+use serde::Deserialize;
+use std::fmt::Write;
 use std::fs;
-use std::path::Path;
-use std::path::PathBuf;
-use std::str;
-
-#[derive(Debug, Clone, PartialEq)]
-enum FontFormat {
-    TrueType,
-    Woff,
-    Woff2,
-    Unknown,
-}
-
-impl FontFormat {
-    fn from_str(s: &str) -> Self {
-        match s.to_lowercase().as_str() {
-            "truetype" => FontFormat::TrueType,
-            "woff" => FontFormat::Woff,
-            "woff2" => FontFormat::Woff2,
-            _ => FontFormat::Unknown,
-        }
-    }
+use std::io::{self, BufRead, IsTerminal};
+use std::num::NonZeroUsize;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
 
-    fn to_extension(&self) -> String {
-        match self {
-            FontFormat::TrueType => "ttf".to_string(),
-            FontFormat::Woff => "woff".to_string(),
-            FontFormat::Woff2 => "woff2".to_string(),
-            FontFormat::Unknown => String::new(),
-        }
-    }
+/// What to do with the fonts from the URLs.
+enum Action {
+    /// Download the fonts and write their CSS.
+    Download,
+    /// Print a table of the fonts without downloading them.
+    List,
+    /// Print the subsets the fonts are in without downloading them.
+    ListSubsets,
+    /// Check the font files on disk against the sizes Google Fonts reports for them.
+    Verify,
+    /// Print the URL of every font file without downloading them.
+    PrintUrls,
+    /// Print the rewritten CSS of every font without downloading any font files.
+    PrintCss,
+    /// Download the fonts, and again every time the config file or URL file changes.
+    Watch,
 }
 
-struct Args {
-    urls: Vec<String>,
-    output_dir: PathBuf,
-    overwrite: bool,
-    quiet: bool,
-    verbose: bool,
-    fonts_prefix_in_css: String,
-}
-
-struct FontInfo {
-    css: String,
-    writing_system_name: String,
-}
-
-fn split_css_into_fonts(css: &str) -> Vec<FontInfo> {
-    let mut font_infos = Vec::new();
-    let mut pos = 0;
-
-    while pos < css.len() {
-        // Find start of comment
-        if let Some(comment_start) = css[pos..].find("/*") {
-            let comment_start = pos + comment_start;
-
-            // Find end of comment
-            if let Some(comment_end) = css[comment_start..].find("*/") {
-                let comment_end = comment_start + comment_end + 2; // +2 for "*/"
-                                                                   // Extract writing system name without the comment markers
-                let writing_system_name =
-                    css[comment_start + 2..comment_end - 2].trim().to_string();
-
-                // Find next comment start or end of string
-                let next_comment_start = css[comment_end..]
-                    .find("/*")
-                    .map_or(css.len(), |i| comment_end + i);
-
-                // Extract CSS content between comments
-                let css_content = css[comment_end..next_comment_start].trim().to_string();
-
-                if !css_content.is_empty() {
-                    font_infos.push(FontInfo {
-                        css: css_content,
-                        writing_system_name,
-                    });
-                }
-
-                pos = next_comment_start;
-
-                // If we've reached the end of the string, break
-                if pos >= css.len() {
-                    break;
-                }
-            } else {
-                break;
-            }
-        } else {
-            // No more comments found
-            if pos == 0 {
-                font_infos.push(FontInfo {
-                    css: css.to_string(),
-                    writing_system_name: String::new(),
-                });
-            } else {
-                // Get the remaining CSS after the last comment
-                let remaining_css = css[pos..].trim().to_string();
-                if !remaining_css.is_empty() {
-                    // Use the last writing system name if we have one, or empty string if not
-                    let writing_system_name = font_infos
-                        .last()
-                        .map_or_else(String::new, |info| info.writing_system_name.clone());
-
-                    font_infos.push(FontInfo {
-                        css: remaining_css,
-                        writing_system_name,
-                    });
-                }
-            }
-            break;
-        }
-    }
-
-    font_infos
-}
-
-impl FontInfo {
-    fn get_font_family(&self) -> String {
-        self.css
-            .split("font-family: '")
-            .nth(1)
-            .unwrap()
-            .split("';")
-            .next()
-            .unwrap()
-            .to_string()
-    }
-
-    fn get_font_style(&self) -> String {
-        self.css
-            .split("font-style: ")
-            .nth(1)
-            .unwrap()
-            .split(';')
-            .next()
-            .unwrap()
-            .to_string()
-    }
-
-    fn get_font_weight(&self) -> String {
-        self.css
-            .split("font-weight: ")
-            .nth(1)
-            .unwrap()
-            .split(';')
-            .next()
-            .unwrap()
-            .to_string()
-    }
-
-    fn get_font_stretch(&self) -> Option<String> {
-        // Check if font-stretch property exists in the CSS
-        if self.css.contains("font-stretch:") {
-            // Extract the font-stretch value
-            Some(
-                self.css
-                    .split("font-stretch: ")
-                    .nth(1)
-                    .unwrap()
-                    .split(';')
-                    .next()
-                    .unwrap()
-                    .to_string(),
-            )
-        } else {
-            // Return None if font-stretch property is not present
-            None
-        }
-    }
-
-    fn get_font_display(&self) -> String {
-        self.css
-            .split("font-display: ")
-            .nth(1)
-            .unwrap()
-            .split(';')
-            .next()
-            .unwrap()
-            .to_string()
-    }
+/// How often to check whether the watched files have changed.
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
 
-    fn get_font_url_and_format(&self) -> (String, FontFormat) {
-        // Extract the URL and format from the CSS source property
-
-        let src_part = self
-            .css
-            .split("src: ")
-            .nth(1)
-            .unwrap()
-            .split(';')
-            .next()
-            .unwrap();
+/// The config file read from the current directory if `--config` isn't given.
+const DEFAULT_CONFIG_FILE: &str = "google-fonts-downloader.toml";
 
-        // Extract the URL from the url() part
-        let url_start = src_part.find("url(").unwrap() + 4;
-        let url_end = src_part.find(')').unwrap();
-        let url = src_part[url_start..url_end].trim_matches('"').to_string();
-
-        // Extract the format from the format() part
-        let format_start = src_part.find("format('").unwrap() + 8;
-        let format_end = src_part.find("')").unwrap();
-        let format_str = src_part[format_start..format_end].to_string();
-        let format = FontFormat::from_str(&format_str);
-
-        (url, format)
-    }
-
-    fn get_font_url(&self) -> String {
-        self.get_font_url_and_format().0
-    }
-
-    fn get_font_format(&self) -> FontFormat {
-        self.get_font_url_and_format().1
-    }
-
-    fn get_font_filename(&self) -> String {
-        format!(
-            "{}-{}-{}-{}.{}",
-            self.get_font_family().to_lowercase().replace(' ', "-"),
-            self.get_font_weight(),
-            self.get_font_style(),
-            self.writing_system_name,
-            self.get_font_format().to_extension()
-        )
-    }
-
-    fn get_css_filename(&self) -> String {
-        format!(
-            "{}-{}-{}-{}.css",
-            self.get_font_family().to_lowercase().replace(' ', "-"),
-            self.get_font_weight(),
-            self.get_font_style(),
-            self.writing_system_name
-        )
-    }
-
-    fn get_new_css(&self, font_prefix: &str) -> String {
-        let original_url = self.get_font_url();
-        let font_filename = self.get_font_filename();
-        let new_url = format!("{font_prefix}/{font_filename}");
-        self.css.replace(&original_url, &new_url)
-    }
+/// Default options read from a config file, which are overridden by command-line options.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct ConfigFile {
+    output: Option<PathBuf>,
+    fonts_prefix: Option<String>,
+    format: Option<String>,
+    subsets: Vec<String>,
+    urls: Vec<String>,
 }
 
-fn parse_args() -> Args {
-    let matches = command!()
+#[allow(clippy::too_many_lines)]
+fn build_cli() -> Command {
+    command!()
         .arg(
             Arg::new("overwrite")
                 .short('w')
                 .long("overwrite")
                 .action(ArgAction::SetTrue)
-                .help("Whether to overwrite existing files."),
+                .help("Whether to overwrite existing files, both CSS and font files."),
+        )
+        .arg(
+            Arg::new("overwrite-css")
+                .long("overwrite-css")
+                .action(ArgAction::SetTrue)
+                .help("Whether to overwrite existing CSS files, keeping existing font files."),
+        )
+        .arg(
+            Arg::new("overwrite-fonts")
+                .long("overwrite-fonts")
+                .action(ArgAction::SetTrue)
+                .help("Whether to overwrite existing font files, keeping existing CSS files."),
+        )
+        .arg(
+            Arg::new("list")
+                .short('l')
+                .long("list")
+                .action(ArgAction::SetTrue)
+                .help("Print a table of the fonts each URL contains without downloading or writing anything."),
+        )
+        .arg(
+            Arg::new("list-subsets")
+                .long("list-subsets")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["list", "verify", "print-urls", "print-css", "watch", "subset", "text"])
+                .help("Print the subsets the URLs' fonts are in, one per line, without downloading or writing anything."),
+        )
+        .arg(
+            Arg::new("completions")
+                .long("completions")
+                .value_name("SHELL")
+                .value_parser(value_parser!(Shell))
+                .hide(true)
+                .help("Print a completion script for a shell, like bash, zsh, fish or powershell, and exit."),
+        )
+        .arg(
+            Arg::new("verify")
+                .long("verify")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["list", "offline"])
+                .help("Check the font files in the output directory are the same size as the ones Google Fonts serves, without downloading or writing anything."),
+        )
+        .arg(
+            Arg::new("print-urls")
+                .long("print-urls")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["list", "verify", "verbose"])
+                .help("Print the URL of every font file, one per line, without downloading or writing anything."),
+        )
+        .arg(
+            Arg::new("print-css")
+                .long("print-css")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["list", "verify", "print-urls", "verbose", "no-css"])
+                .help("Print the rewritten CSS of every font as one stylesheet, without downloading or writing anything."),
+        )
+        .arg(
+            Arg::new("update")
+                .short('u')
+                .long("update")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("overwrite")
+                .help("Only overwrite existing files if their contents have changed."),
+        )
+        .arg(
+            Arg::new("dry-run")
+                .short('n')
+                .long("dry-run")
+                .action(ArgAction::SetTrue)
+                .help("Show which files would be written without writing anything."),
         )
         .arg(
             Arg::new("quiet")
                 .short('q')
                 .long("quiet")
                 .action(ArgAction::SetTrue)
-                .help("Suppress informational output, including verbose output."),
+                .conflicts_with("verbose")
+                .help("Suppress informational output."),
+        )
+        .arg(
+            Arg::new("color")
+                .long("color")
+                .value_name("WHEN")
+                .value_parser(["auto", "always", "never"])
+                .default_value("auto")
+                .help("Colour the output, one of auto, always or never. auto only colours output to a terminal, and not if NO_COLOR is set."),
+        )
+        .arg(
+            Arg::new("json-logs")
+                .long("json-logs")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["verbose", "list", "verify", "print-urls", "print-css"])
+                .help("Print each event, like a file being written, as a line of JSON instead of text."),
         )
         .arg(
             Arg::new("verbose")
@@ -272,184 +168,1171 @@ fn parse_args() -> Args {
                 .default_value("./")
                 .help("Prefix for font files in CSS output."),
         )
+        .arg(
+            Arg::new("base-url")
+                .long("base-url")
+                .value_name("URL")
+                .value_parser(parse_base_url)
+                .conflicts_with("fonts-prefix")
+                .help("An absolute URL the font files will be served from, like https://cdn.example.com/fonts, used in the CSS instead of the fonts prefix."),
+        )
+        .arg(
+            Arg::new("format")
+                .short('f')
+                .long("format")
+                .value_parser(["truetype", "woff", "woff2"])
+                .default_value("woff2")
+                .help("The font format to request from Google Fonts."),
+        )
+        .arg(
+            Arg::new("all-formats")
+                .long("all-formats")
+                .action(ArgAction::SetTrue)
+                .help("Download every format listed in a font's sources instead of only the first one."),
+        )
+        .arg(
+            Arg::new("prefer-format")
+                .long("prefer-format")
+                .value_name("FORMATS")
+                .value_parser(parse_font_format)
+                .value_delimiter(',')
+                .default_value("woff2,woff,truetype")
+                .conflicts_with("all-formats")
+                .help("The order to prefer formats in when a font lists several, downloading only the first one it has."),
+        )
+        .arg(
+            Arg::new("combine-css")
+                .long("combine-css")
+                .value_name("FILENAME")
+                .num_args(0..=1)
+                .default_missing_value("fonts.css")
+                .help("Write a single CSS file containing every font instead of one CSS file per font."),
+        )
+        .arg(
+            Arg::new("css-grouping")
+                .long("css-grouping")
+                .value_name("GROUPING")
+                .value_parser(CssGrouping::parse)
+                .default_value("per-block")
+                .conflicts_with("combine-css")
+                .help("How to group the CSS into files, one of per-block, per-family or single."),
+        )
+        .arg(
+            Arg::new("index-css")
+                .long("index-css")
+                .value_name("FILENAME")
+                .num_args(0..=1)
+                .default_missing_value("index.css")
+                .conflicts_with("combine-css")
+                .help("Also write a CSS file that imports the CSS file of every font."),
+        )
+        .arg(
+            Arg::new("fallback-stack")
+                .long("fallback-stack")
+                .action(ArgAction::SetTrue)
+                .help("Add a .font-<family> rule with a fallback font stack for every family to the combined, per-family or index CSS."),
+        )
+        .arg(
+            Arg::new("no-css")
+                .long("no-css")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["combine-css", "css-grouping", "index-css", "fallback-stack", "css-only"])
+                .help("Only write the font files, without any CSS."),
+        )
+        .arg(
+            Arg::new("css-only")
+                .long("css-only")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["manifest", "sri", "checksums"])
+                .help("Only write the CSS, without downloading the font files."),
+        )
+        .arg(
+            Arg::new("decompress-to-ttf")
+                .long("decompress-to-ttf")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("verify")
+                .help("Decompress WOFF2 font files and save them as TTF files, with the CSS pointing at the TTF files."),
+        )
+        .arg(
+            Arg::new("manifest")
+                .long("manifest")
+                .value_name("PATH")
+                .value_parser(value_parser!(PathBuf))
+                .help("Write a manifest describing every downloaded font to this path."),
+        )
+        .arg(
+            Arg::new("zip")
+                .long("zip")
+                .value_name("PATH")
+                .value_parser(value_parser!(PathBuf))
+                .conflicts_with_all(["update", "watch", "if-modified-since"])
+                .help("Pack the font files and CSS into a zip archive at this path instead of writing them to the output directory."),
+        )
+        .arg(
+            Arg::new("manifest-format")
+                .long("manifest-format")
+                .value_name("FORMAT")
+                .value_parser(ManifestFormat::parse)
+                .default_value("json")
+                .help("The format to write the manifest in, one of json, csv or toml."),
+        )
+        .arg(
+            Arg::new("proxy")
+                .long("proxy")
+                .value_name("URL")
+                .help("Send every request through this proxy, supporting http://, https://, socks5:// and socks5h:// URLs."),
+        )
+        .arg(
+            Arg::new("user-agent")
+                .long("user-agent")
+                .value_name("USER_AGENT")
+                .help("Send this User-Agent instead of one for a browser that supports the requested format."),
+        )
+        .arg(
+            Arg::new("retries")
+                .long("retries")
+                .value_parser(value_parser!(u32))
+                .default_value("3")
+                .help("The number of times to retry a failed download."),
+        )
+        .arg(
+            Arg::new("cache-dir")
+                .long("cache-dir")
+                .value_name("DIR")
+                .value_parser(expand_path)
+                .help("Save the downloaded CSS in this directory, so it can be reused with --offline."),
+        )
+        .arg(
+            Arg::new("offline")
+                .long("offline")
+                .action(ArgAction::SetTrue)
+                .requires("cache-dir")
+                .help("Read the CSS from the cache directory, and only download font files that aren't in the output directory."),
+        )
+        .arg(
+            Arg::new("if-modified-since")
+                .long("if-modified-since")
+                .action(ArgAction::SetTrue)
+                .requires("cache-dir")
+                .conflicts_with("offline")
+                .help("Ask Google Fonts if the cached CSS has changed, and skip downloading the font files if it hasn't."),
+        )
+        .arg(
+            Arg::new("timeout")
+                .long("timeout")
+                .value_name("SECONDS")
+                .value_parser(value_parser!(u64).range(1..))
+                .default_value("30")
+                .help("How many seconds to wait for each request before giving up on it."),
+        )
+        .arg(
+            Arg::new("rate-limit")
+                .long("rate-limit")
+                .value_name("N")
+                .value_parser(parse_rate_limit)
+                .help("Send at most this many requests per second, to avoid being rate limited by Google Fonts."),
+        )
+        .arg(
+            Arg::new("max-bytes")
+                .long("max-bytes")
+                .value_name("BYTES")
+                .value_parser(value_parser!(u64).range(1..))
+                .help("Stop with an error if a font file or CSS file is larger than this many bytes."),
+        )
+        .arg(
+            Arg::new("filename-template")
+                .long("filename-template")
+                .value_name("TEMPLATE")
+                .value_parser(FilenameTemplate::parse)
+                .default_value("{family}-{weight}-{style}-{stretch}-{subset}.{ext}")
+                .help("The pattern used to name font and CSS files, using the placeholders {family}, {weight}, {style}, {stretch}, {subset}, {ext} and {hash}."),
+        )
+        .arg(
+            Arg::new("hash-algo")
+                .long("hash-algo")
+                .value_name("ALGORITHM")
+                .value_parser(HashAlgorithm::parse)
+                .default_value("sha256")
+                .help("The hash algorithm for the {hash} filename placeholder, which hashes the font's URL, one of sha256, sha1, md5 or xxhash."),
+        )
+        .arg(
+            Arg::new("hash-encoding")
+                .long("hash-encoding")
+                .value_name("ENCODING")
+                .value_parser(HashEncoding::parse)
+                .default_value("hex")
+                .help("How the {hash} filename placeholder writes the hash, either hex or base62."),
+        )
+        .arg(
+            Arg::new("hash-length")
+                .long("hash-length")
+                .value_name("LENGTH")
+                .value_parser(value_parser!(u8).range(1..=64))
+                .default_value("8")
+                .help("How many characters of the hash of the font's URL the {hash} filename placeholder uses."),
+        )
+        .arg(
+            Arg::new("layout")
+                .long("layout")
+                .value_parser(OutputLayout::parse)
+                .default_value("flat")
+                .help("How to arrange font files in the output directory, one of flat, family, subset or family-subset."),
+        )
+        .arg(
+            Arg::new("keep-subset-comments")
+                .long("keep-subset-comments")
+                .action(ArgAction::SetTrue)
+                .overrides_with("strip-comments")
+                .help("Write the subset comment, like /* latin */, above each block in the CSS. This is the default."),
+        )
+        .arg(
+            Arg::new("strip-comments")
+                .long("strip-comments")
+                .action(ArgAction::SetTrue)
+                .overrides_with("keep-subset-comments")
+                .help("Don't write the subset comment above each block in the CSS."),
+        )
+        .arg(
+            Arg::new("font-display")
+                .long("font-display")
+                .value_parser(["auto", "block", "swap", "fallback", "optional"])
+                .help("Set the font-display descriptor in the CSS, instead of the one from Google Fonts."),
+        )
+        .arg(
+            Arg::new("add-local")
+                .long("add-local")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("no-css")
+                .help("Start the src in the CSS with local('Family'), so a copy of the font that's already installed is used."),
+        )
+        .arg(
+            Arg::new("preload-html")
+                .long("preload-html")
+                .value_name("PATH")
+                .value_parser(value_parser!(PathBuf))
+                .help("Write <link rel=\"preload\"> tags for every font file to this path."),
+        )
+        .arg(
+            Arg::new("preview")
+                .long("preview")
+                .value_name("PATH")
+                .value_parser(value_parser!(PathBuf))
+                .conflicts_with("no-css")
+                .help("Write an HTML page showing a sample of every font to this path."),
+        )
+        .arg(
+            Arg::new("scss-vars")
+                .long("scss-vars")
+                .value_name("PATH")
+                .value_parser(value_parser!(PathBuf))
+                .help("Write a SCSS variable with the path of every font file to this path."),
+        )
+        .arg(
+            Arg::new("sri")
+                .long("sri")
+                .action(ArgAction::SetTrue)
+                .help("Add a Subresource Integrity hash for every font file to the manifest and preload tags."),
+        )
+        .arg(
+            Arg::new("checksums")
+                .long("checksums")
+                .action(ArgAction::SetTrue)
+                .help("Write a .sha256 file with the SHA-256 digest of every font file next to it."),
+        )
+        .arg(
+            Arg::new("precompress")
+                .long("precompress")
+                .value_name("FORMATS")
+                .value_parser(Precompression::parse)
+                .value_delimiter(',')
+                .action(ArgAction::Append)
+                .conflicts_with("css-only")
+                .help("Also write compressed copies of every CSS and font file, like 'gzip,br', skipping WOFF and WOFF2 fonts."),
+        )
+        .arg(
+            Arg::new("subset")
+                .short('s')
+                .long("subset")
+                .action(ArgAction::Append)
+                .help("Only download fonts for this writing system, like 'latin' or 'latin*', can be used multiple times."),
+        )
+        .arg(
+            Arg::new("force-https")
+                .long("force-https")
+                .action(ArgAction::SetTrue)
+                .help("Upgrade http:// CSS and font file URLs to https://."),
+        )
+        .arg(
+            Arg::new("codepoints")
+                .long("codepoints")
+                .value_name("RANGES")
+                .value_parser(parse_unicode_range)
+                .action(ArgAction::Append)
+                .help("Only download fonts whose unicode-range includes one of these codepoints, like 'U+0000-00FF', can be used multiple times."),
+        )
+        .arg(
+            Arg::new("weights")
+                .long("weights")
+                .value_name("WEIGHTS")
+                .value_parser(value_parser!(u16).range(1..=1000))
+                .value_delimiter(',')
+                .action(ArgAction::Append)
+                .help("Only download fonts with one of these weights, like '400,700', or variable fonts covering one of them."),
+        )
+        .arg(
+            Arg::new("only-family")
+                .long("only-family")
+                .value_name("FAMILY")
+                .action(ArgAction::Append)
+                .help("Only download fonts in this family, like 'Open Sans', ignoring case, can be used multiple times."),
+        )
+        .arg(
+            Arg::new("allow-host")
+                .long("allow-host")
+                .value_name("HOST")
+                .action(ArgAction::Append)
+                .help("Also allow font files to be downloaded from this host, as well as fonts.gstatic.com, can be used multiple times."),
+        )
+        .arg(
+            Arg::new("text")
+                .long("text")
+                .value_name("TEXT")
+                .conflicts_with("subset")
+                .help("Only download the glyphs needed to display this text, in a subset named after a hash of the text."),
+        )
+        .arg(
+            Arg::new("jobs")
+                .short('j')
+                .long("font-jobs")
+                .visible_alias("jobs")
+                .value_name("N")
+                .value_parser(value_parser!(NonZeroUsize))
+                .default_value("4")
+                .help("The number of font files to download at the same time."),
+        )
+        .arg(
+            Arg::new("url-jobs")
+                .long("url-jobs")
+                .value_name("N")
+                .value_parser(value_parser!(NonZeroUsize))
+                .default_value("2")
+                .help("The number of CSS URLs to download at the same time."),
+        )
+        .arg(
+            Arg::new("keep-going")
+                .long("keep-going")
+                .action(ArgAction::SetTrue)
+                .help("Carry on with the other CSS URLs when one fails, and report every failure at the end."),
+        )
         .arg(
             Arg::new("output")
                 .short('o')
                 .long("output")
-                .value_parser(value_parser!(PathBuf))
+                .value_parser(expand_path)
                 .default_value("./fonts")
-                .help("The name of the output directory, will be created if it doesn't exist."),
+                .help("The name of the output directory, will be created if it doesn't exist. Use - with --css-only to print the combined CSS to stdout instead."),
+        )
+        .arg(
+            Arg::new("config")
+                .short('c')
+                .long("config")
+                .value_name("PATH")
+                .value_parser(value_parser!(PathBuf))
+                .help("Read default options from this TOML file instead of ./google-fonts-downloader.toml."),
+        )
+        .arg(
+            Arg::new("css-output")
+                .long("css-output")
+                .value_name("DIR")
+                .value_parser(expand_path)
+                .help("The directory to write CSS files to, defaults to the output directory."),
+        )
+        .arg(
+            Arg::new("font")
+                .long("font")
+                .value_name("SPEC")
+                .action(ArgAction::Append)
+                .value_parser(parse_font_spec)
+                .help("Download a font from a family and optional axes, like 'Roboto:wght@400;700', instead of a URL."),
+        )
+        .arg(
+            Arg::new("display")
+                .long("display")
+                .value_name("VALUE")
+                .value_parser(["auto", "block", "swap", "fallback", "optional"])
+                .help("The display parameter to add to the URLs built from --font, which Google Fonts uses as the font-display descriptor in the CSS."),
+        )
+        .arg(
+            Arg::new("url-file")
+                .long("url-file")
+                .value_name("PATH")
+                .value_parser(value_parser!(PathBuf))
+                .help("Read URLs from a file, one per line, ignoring empty lines and lines starting with '#'."),
+        )
+        .arg(
+            Arg::new("watch")
+                .long("watch")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["list", "verify", "print-urls", "print-css"])
+                .help("Download the fonts, then download them again every time the config file or URL file changes, only writing files that changed."),
         )
         .arg(
             Arg::new("url")
                 .action(ArgAction::Append) // Accept multiple values
-                .required(true),
+                .help("Google Fonts CSS URLs to download, or '-' to read URLs from stdin. Prefix a URL with 'LABEL=' to write its font files to a LABEL subdirectory."),
         )
-        .get_matches();
+}
 
-    Args {
-        overwrite: matches.get_flag("overwrite"),
-        quiet: matches.get_flag("quiet"),
-        verbose: matches.get_flag("verbose"),
-        fonts_prefix_in_css: matches
-            .get_one::<String>("fonts-prefix")
-            .unwrap()
+fn parse_args() -> (Config, Action) {
+    let mut cli = build_cli();
+    let matches = cli.get_matches_mut();
+    if let Some(shell) = matches.get_one::<Shell>("completions") {
+        print_completions(&mut cli, *shell);
+    }
+    match config_from_args(&mut cli, &matches) {
+        Ok(config_and_action) => config_and_action,
+        Err(e) => e.exit(),
+    }
+}
+
+/// The config and action from the command-line options, the config file and the URLs, or an error to show with the usage.
+/// The config file and URL file are read again every time, so watch mode can pick up changes to them.
+fn config_from_args(
+    cli: &mut Command,
+    matches: &ArgMatches,
+) -> Result<(Config, Action), clap::Error> {
+    let config_file = match read_config_file(matches.get_one::<PathBuf>("config")) {
+        Ok(config_file) => config_file,
+        Err(e) => return Err(cli.error(ErrorKind::Io, e)),
+    };
+    // Options from the command line take priority over the config file, which takes priority over the defaults
+    let from_command_line = |id| matches.value_source(id) == Some(ValueSource::CommandLine);
+
+    if matches.get_flag("watch")
+        && matches
+            .get_many::<String>("url")
+            .unwrap_or_default()
+            .any(|url| url == "-")
+    {
+        return Err(cli.error(
+            ErrorKind::ArgumentConflict,
+            "--watch can't read URLs from stdin again when something changes, use --url-file instead.",
+        ));
+    }
+    let mut urls = collect_urls(cli, matches)?;
+    if urls.is_empty() {
+        urls = config_file.urls;
+    }
+    if urls.is_empty() {
+        return Err(cli.error(
+            ErrorKind::MissingRequiredArgument,
+            "No URLs were given on the command line, with --font, with --url-file or in the config file.",
+        ));
+    }
+
+    let format = match config_file.format {
+        Some(format) if !from_command_line("format") => {
+            if !["truetype", "woff", "woff2"].contains(&format.as_str()) {
+                return Err(cli.error(
+                    ErrorKind::InvalidValue,
+                    format!("Invalid format '{format}' in the config file, expected one of truetype, woff or woff2."),
+                ));
+            }
+            format
+        }
+        _ => matches.get_one::<String>("format").unwrap().clone(),
+    };
+    let subsets: Vec<String> = match matches.get_many::<String>("subset") {
+        Some(subsets) => subsets.cloned().collect(),
+        // Listing the subsets shouldn't only list the ones the config file already asks for
+        None if matches.get_flag("list-subsets") => Vec::new(),
+        None => config_file.subsets,
+    };
+    let output_dir = match config_file.output {
+        Some(output_dir) if !from_command_line("output") => {
+            match expand_path(&output_dir.to_string_lossy()) {
+                Ok(output_dir) => output_dir,
+                Err(e) => return Err(cli.error(ErrorKind::InvalidValue, e)),
+            }
+        }
+        _ => matches.get_one::<PathBuf>("output").unwrap().clone(),
+    };
+    let fonts_prefix = match (
+        matches.get_one::<String>("base-url"),
+        config_file.fonts_prefix,
+    ) {
+        (Some(base_url), _) => base_url.clone(),
+        (None, Some(fonts_prefix)) if !from_command_line("fonts-prefix") => fonts_prefix,
+        _ => matches.get_one::<String>("fonts-prefix").unwrap().clone(),
+    };
+
+    // There's only one thing that can be streamed to stdout, which is the combined CSS
+    let output_is_stdout = output_dir == Path::new("-");
+    if output_is_stdout && (!matches.get_flag("css-only") || matches.get_flag("watch")) {
+        return Err(cli.error(
+            ErrorKind::ArgumentConflict,
+            "--output - prints the combined CSS to stdout, so it can only be used with --css-only and without --watch.",
+        ));
+    }
+    let action = action_from_matches(matches, output_is_stdout);
+
+    // With one CSS file per block, the rules would only be repeated in every file
+    if matches.get_flag("fallback-stack")
+        && !matches.contains_id("combine-css")
+        && !matches.contains_id("index-css")
+        && !matches!(action, Action::PrintCss)
+        && matches.get_one::<CssGrouping>("css-grouping") == Some(&CssGrouping::PerBlock)
+    {
+        return Err(cli.error(
+            ErrorKind::MissingRequiredArgument,
+            "--fallback-stack needs a CSS file to add its rules to, use it with --combine-css, --css-grouping or --index-css.",
+        ));
+    }
+
+    let config = Config {
+        urls,
+        format: FontFormat::from_str(&format),
+        subsets,
+        output_dir,
+        fonts_prefix_in_css: fonts_prefix
             .trim_end_matches('/') // Remove trailing slash
             .to_string(),
-        output_dir: matches.get_one::<PathBuf>("output").unwrap().clone(),
-        urls: matches
-            .get_many::<String>("url")
+        ..config_from_matches(matches)
+    };
+    Ok((config, action))
+}
+
+/// What to do with the config, which is to download the fonts unless another action is asked for.
+fn action_from_matches(matches: &ArgMatches, output_is_stdout: bool) -> Action {
+    if matches.get_flag("list") {
+        Action::List
+    } else if matches.get_flag("list-subsets") {
+        Action::ListSubsets
+    } else if matches.get_flag("verify") {
+        Action::Verify
+    } else if matches.get_flag("print-urls") {
+        Action::PrintUrls
+    } else if matches.get_flag("print-css") || output_is_stdout {
+        Action::PrintCss
+    } else if matches.get_flag("watch") {
+        Action::Watch
+    } else {
+        Action::Download
+    }
+}
+
+/// Parse a font format like `woff2`, for `--prefer-format`.
+fn parse_font_format(format: &str) -> Result<FontFormat, String> {
+    match FontFormat::from_str(format) {
+        FontFormat::Unknown => Err(format!(
+            "Unknown font format '{format}', expected one of woff2, woff, truetype, opentype, embedded-opentype or svg."
+        )),
+        format => Ok(format),
+    }
+}
+
+/// Every value given for an option that can be used multiple times, or none if it wasn't used.
+fn all_values<T: Clone + Send + Sync + 'static>(matches: &ArgMatches, id: &str) -> Vec<T> {
+    matches
+        .get_many::<T>(id)
+        .unwrap_or_default()
+        .cloned()
+        .collect()
+}
+
+/// The config from the command-line options alone, without any URLs or options from the config file.
+fn config_from_matches(matches: &ArgMatches) -> Config {
+    Config {
+        overwrite_css: matches.get_flag("overwrite") || matches.get_flag("overwrite-css"),
+        overwrite_fonts: matches.get_flag("overwrite") || matches.get_flag("overwrite-fonts"),
+        // Running again when something changes shouldn't rewrite the files that didn't
+        update: matches.get_flag("update") || matches.get_flag("watch"),
+        dry_run: matches.get_flag("dry-run"),
+        quiet: matches.get_flag("quiet"),
+        verbose: matches.get_flag("verbose"),
+        color: match matches.get_one::<String>("color").unwrap().as_str() {
+            "always" => true,
+            "never" => false,
+            // https://no-color.org says any non-empty value of NO_COLOR turns colour off
+            _ => {
+                io::stderr().is_terminal()
+                    && std::env::var_os("NO_COLOR").is_none_or(|no_color| no_color.is_empty())
+            }
+        },
+        json_logs: matches.get_flag("json-logs"),
+        css_output_dir: matches.get_one::<PathBuf>("css-output").cloned(),
+        jobs: *matches.get_one::<NonZeroUsize>("jobs").unwrap(),
+        url_jobs: *matches.get_one::<NonZeroUsize>("url-jobs").unwrap(),
+        keep_going: matches.get_flag("keep-going"),
+        all_formats: matches.get_flag("all-formats"),
+        prefer_formats: all_values(matches, "prefer-format"),
+        combine_css: matches.get_one::<String>("combine-css").cloned(),
+        css_grouping: *matches.get_one::<CssGrouping>("css-grouping").unwrap(),
+        index_css: matches.get_one::<String>("index-css").cloned(),
+        fallback_stack: matches.get_flag("fallback-stack"),
+        no_css: matches.get_flag("no-css"),
+        css_only: matches.get_flag("css-only"),
+        decompress_to_ttf: matches.get_flag("decompress-to-ttf"),
+        manifest: matches.get_one::<PathBuf>("manifest").cloned(),
+        manifest_format: *matches
+            .get_one::<ManifestFormat>("manifest-format")
+            .unwrap(),
+        zip: matches.get_one::<PathBuf>("zip").cloned(),
+        subset_comments: !matches.get_flag("strip-comments"),
+        font_display: matches.get_one::<String>("font-display").cloned(),
+        add_local: matches.get_flag("add-local"),
+        preload_html: matches.get_one::<PathBuf>("preload-html").cloned(),
+        scss_vars: matches.get_one::<PathBuf>("scss-vars").cloned(),
+        preview: matches.get_one::<PathBuf>("preview").cloned(),
+        sri: matches.get_flag("sri"),
+        checksums: matches.get_flag("checksums"),
+        precompress: all_values(matches, "precompress"),
+        proxy: matches.get_one::<String>("proxy").cloned(),
+        user_agent: matches.get_one::<String>("user-agent").cloned(),
+        families: all_values(matches, "only-family"),
+        weights: all_values(matches, "weights"),
+        force_https: matches.get_flag("force-https"),
+        codepoints: matches
+            .get_many::<Vec<RangeInclusive<u32>>>("codepoints")
             .unwrap_or_default()
-            .map(std::string::ToString::to_string)
+            .flatten()
+            .cloned()
             .collect(),
+        allowed_hosts: std::iter::once(DEFAULT_ALLOWED_HOST.to_string())
+            .chain(
+                matches
+                    .get_many::<String>("allow-host")
+                    .unwrap_or_default()
+                    .cloned(),
+            )
+            .collect(),
+        text: matches.get_one::<String>("text").cloned(),
+        retries: *matches.get_one::<u32>("retries").unwrap(),
+        cache_dir: matches.get_one::<PathBuf>("cache-dir").cloned(),
+        offline: matches.get_flag("offline"),
+        if_modified_since: matches.get_flag("if-modified-since"),
+        timeout: Duration::from_secs(*matches.get_one::<u64>("timeout").unwrap()),
+        rate_limit: matches.get_one::<f64>("rate-limit").copied(),
+        max_bytes: matches.get_one::<u64>("max-bytes").copied(),
+        filename_template: matches
+            .get_one::<FilenameTemplate>("filename-template")
+            .unwrap()
+            .clone()
+            .with_hash(
+                *matches.get_one::<HashAlgorithm>("hash-algo").unwrap(),
+                usize::from(*matches.get_one::<u8>("hash-length").unwrap()),
+            )
+            .with_hash_encoding(*matches.get_one::<HashEncoding>("hash-encoding").unwrap()),
+        layout: *matches.get_one::<OutputLayout>("layout").unwrap(),
+        urls: Vec::new(),
+        format: FontFormat::from_str(matches.get_one::<String>("format").unwrap()),
+        subsets: all_values(matches, "subset"),
+        output_dir: matches.get_one::<PathBuf>("output").unwrap().clone(),
+        fonts_prefix_in_css: matches.get_one::<String>("fonts-prefix").unwrap().clone(),
     }
 }
 
-fn ensure_output_dir(output_dir: &PathBuf) -> std::io::Result<()> {
-    if !output_dir.exists() {
-        fs::create_dir_all(output_dir)?;
+/// Check a base URL is an absolute HTTP or HTTPS URL that font filenames can be added to the end of.
+fn parse_base_url(base_url: &str) -> Result<String, String> {
+    let url = reqwest::Url::parse(base_url)
+        .map_err(|e| format!("Invalid base URL '{base_url}': {e}."))?;
+    if !["http", "https"].contains(&url.scheme()) {
+        return Err(format!(
+            "Invalid base URL '{base_url}': the scheme must be http or https."
+        ));
+    }
+    if url.query().is_some() || url.fragment().is_some() {
+        return Err(format!(
+            "Invalid base URL '{base_url}': it can't have a query or fragment."
+        ));
     }
-    Ok(())
+    Ok(base_url.trim_end_matches('/').to_string())
 }
 
-fn download_fonts(
-    url: &str,
-    output_dir: &Path,
-    overwrite: bool,
-    quiet: bool,
-    verbose: bool,
-    fonts_prefix_in_css: &str,
-    client: &reqwest::blocking::Client,
-) -> Result<(), Box<dyn std::error::Error>> {
-    if !quiet {
-        println!("Downloading CSS: '{url}'.");
+/// Build a `css2` URL from a family and optional axes, like `Roboto:wght@400;700` or `Open Sans:ital,wght@0,400;1,700`.
+/// The `display` parameter is added later, if `--display` is given.
+fn parse_font_spec(spec: &str) -> Result<String, String> {
+    let (family, axes) = match spec.split_once(':') {
+        Some((family, axes)) => (family.trim(), Some(axes.trim())),
+        None => (spec.trim(), None),
+    };
+    if family.is_empty() {
+        return Err(format!("Invalid font '{spec}': the family is missing."));
     }
-    // Google Fonts serves different CSS content based on the User-Agent.
-    // Without a browser-like User-Agent, it returns a simplified version without writing system comments.
-    // Setting a browser User-Agent ensures we get the full CSS with all writing system information.
-    let response = client
-        .get(url)
-        .header("User-Agent", "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36")
-        .send()?;
-    // Parse the response
-    let response_bytes = response.bytes()?;
-    let css_content = str::from_utf8(&response_bytes)?;
+    // Spaces are written as `+` like in the URLs from Google Fonts, and anything else that isn't a letter or digit is percent-encoded
+    let mut url = String::from("https://fonts.googleapis.com/css2?family=");
+    for byte in family.bytes() {
+        match byte {
+            b' ' => url.push('+'),
+            byte if byte.is_ascii_alphanumeric() => url.push(char::from(byte)),
+            byte => {
+                let _ = write!(url, "%{byte:02X}");
+            }
+        }
+    }
+    if let Some(axes) = axes {
+        if axes.is_empty()
+            || !axes
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || [',', ';', '.', '@'].contains(&c))
+        {
+            return Err(format!(
+                "Invalid font '{spec}': the axes should look like 'wght@400;700' or 'ital,wght@0,400;1,700'."
+            ));
+        }
+        url.push(':');
+        url.push_str(axes);
+    }
+    Ok(url)
+}
 
-    if verbose {
-        println!("Downloaded CSS content ({} bytes)", css_content.len());
+/// Check a rate limit is a positive number of requests per second, which can be a fraction like `0.5`.
+fn parse_rate_limit(rate_limit: &str) -> Result<f64, String> {
+    match rate_limit.parse::<f64>() {
+        Ok(rate) if rate.is_finite() && rate > 0.0 => Ok(rate),
+        _ => Err(format!(
+            "Invalid rate limit '{rate_limit}': it must be a positive number of requests per second."
+        )),
     }
+}
 
-    let fonts = split_css_into_fonts(css_content);
+/// Expand a leading `~` to the home directory, and `$VAR`, `${VAR}` and `%VAR%` to the values of environment variables.
+fn expand_path(path: &str) -> Result<PathBuf, String> {
+    expand_path_with(path, |name| std::env::var(name).ok())
+}
 
-    if verbose {
-        println!("Found {} font entries in the CSS", fonts.len());
-    }
+/// Expand a path like `expand_path`, looking up variables with `var`.
+fn expand_path_with(path: &str, var: impl Fn(&str) -> Option<String>) -> Result<PathBuf, String> {
+    let lookup = |name: &str| {
+        var(name).ok_or_else(|| {
+            format!("Invalid path '{path}': the environment variable '{name}' isn't set.")
+        })
+    };
+    let is_name_char = |c: char| c.is_ascii_alphanumeric() || c == '_';
 
-    // Download each font
-    for font in fonts {
-        if !quiet {
-            println!("Downloading font file: '{}'.", font.get_font_url());
+    let mut expanded = String::new();
+    let mut rest = path;
+    // Only `~` on its own or followed by a separator means the home directory, `~user` is left alone
+    if let Some(after) = rest.strip_prefix('~')
+        && (after.is_empty() || after.starts_with(['/', '\\']))
+    {
+        let home = var("HOME").or_else(|| var("USERPROFILE")).ok_or_else(|| {
+            format!("Invalid path '{path}': couldn't find the home directory to expand '~'.")
+        })?;
+        expanded.push_str(&home);
+        rest = after;
+    }
+    while let Some(i) = rest.find(['$', '%']) {
+        expanded.push_str(&rest[..i]);
+        let after = &rest[i + 1..];
+        let (name, remainder) = if rest[i..].starts_with('%') {
+            match after.split_once('%') {
+                Some((name, remainder)) if !name.is_empty() && name.chars().all(is_name_char) => {
+                    (name, remainder)
+                }
+                _ => ("", after),
+            }
+        } else if let Some(braced) = after.strip_prefix('{') {
+            match braced.split_once('}') {
+                Some((name, remainder)) if !name.is_empty() => (name, remainder),
+                _ => ("", after),
+            }
+        } else {
+            let end = after.find(|c| !is_name_char(c)).unwrap_or(after.len());
+            (&after[..end], &after[end..])
+        };
+        if name.is_empty() {
+            // Not a variable, so keep the `$` or `%` as it is
+            expanded.push_str(&rest[i..=i]);
+        } else {
+            expanded.push_str(&lookup(name)?);
         }
+        rest = remainder;
+    }
+    expanded.push_str(rest);
+    Ok(PathBuf::from(expanded))
+}
 
-        if verbose {
-            println!("  Font family: {}", font.get_font_family());
-            println!("  Font style: {}", font.get_font_style());
-            println!("  Font weight: {}", font.get_font_weight());
-            if let Some(stretch) = font.get_font_stretch() {
-                println!("  Font stretch: {stretch}");
+/// Print a completion script for the shell to stdout and exit, before any other arguments are checked.
+fn print_completions(cli: &mut Command, shell: Shell) -> ! {
+    let name = cli.get_name().to_string();
+    clap_complete::generate(shell, cli, name, &mut io::stdout());
+    std::process::exit(0);
+}
+
+/// Collect URLs from the command line, stdin and the URL file.
+fn collect_urls(cli: &mut Command, matches: &ArgMatches) -> Result<Vec<String>, clap::Error> {
+    let mut urls = Vec::new();
+    for url in matches.get_many::<String>("url").unwrap_or_default() {
+        if url == "-" {
+            match read_url_list(io::stdin().lock()) {
+                Ok(stdin_urls) => urls.extend(stdin_urls),
+                Err(e) => {
+                    return Err(cli.error(
+                        ErrorKind::Io,
+                        format!("Failed to read URLs from stdin: {e}"),
+                    ));
+                }
+            }
+        } else {
+            urls.push(url.clone());
+        }
+    }
+    let display = matches.get_one::<String>("display");
+    urls.extend(
+        matches
+            .get_many::<String>("font")
+            .unwrap_or_default()
+            .map(|url| match display {
+                Some(display) => format!("{url}&display={display}"),
+                None => url.clone(),
+            }),
+    );
+    if let Some(url_file) = matches.get_one::<PathBuf>("url-file") {
+        match fs::File::open(url_file).and_then(|file| read_url_list(io::BufReader::new(file))) {
+            Ok(file_urls) => urls.extend(file_urls),
+            Err(e) => {
+                return Err(cli.error(
+                    ErrorKind::Io,
+                    format!("Failed to read URL file '{}': {e}", url_file.display()),
+                ));
             }
-            println!("  Font display: {}", font.get_font_display());
-            println!("  Writing system: {}", font.writing_system_name);
-            println!("  Format: {:?}", font.get_font_format());
-            println!("  Extension: {}", font.get_font_format().to_extension());
         }
+    }
+    Ok(urls)
+}
 
-        let font_file_response = client.get(font.get_font_url()).send()?;
-        let font_file_bytes = font_file_response.bytes()?;
+/// Read the config file from the given path, or from the current directory if it exists.
+fn read_config_file(path: Option<&PathBuf>) -> Result<ConfigFile, String> {
+    let contents = match path {
+        Some(path) => fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read config file '{}': {e}", path.display()))?,
+        None => match fs::read_to_string(DEFAULT_CONFIG_FILE) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(ConfigFile::default()),
+            Err(e) => {
+                return Err(format!(
+                    "Failed to read config file '{DEFAULT_CONFIG_FILE}': {e}"
+                ));
+            }
+        },
+    };
+    let path = path.map_or_else(
+        || DEFAULT_CONFIG_FILE.into(),
+        |path| path.display().to_string(),
+    );
+    toml::from_str(&contents).map_err(|e| format!("Failed to parse config file '{path}': {e}"))
+}
 
-        if verbose {
-            println!("  Downloaded font file ({} bytes)", font_file_bytes.len());
+/// The distinct subsets the fonts are in, in the order they first appear, for `--list-subsets`.
+fn subset_names(fonts: &[FontInfo]) -> Vec<&str> {
+    let mut subsets: Vec<&str> = Vec::new();
+    for font in fonts {
+        if !subsets.contains(&font.writing_system_name.as_str()) {
+            subsets.push(&font.writing_system_name);
         }
+    }
+    subsets
+}
 
-        // Write font file
-        let font_output_path = output_dir.join(font.get_font_filename());
-        if font_output_path.exists() && !overwrite {
-            if !quiet {
-                println!(
-                    "Skipped writing to '{}' (file already exists, use --overwrite to overwrite).",
-                    font_output_path.display()
+/// Describe every font file that doesn't match, or every font file in verbose mode, followed by a count of each, for `--verify`.
+fn verify_report(files: &[VerifiedFile], verbose: bool) -> String {
+    let mut report = String::new();
+    let (mut mismatched, mut missing) = (0, 0);
+    for file in files {
+        let filename = &file.filename;
+        match file.status {
+            VerifyStatus::Matches if verbose => {
+                let _ = writeln!(report, "Matches: '{filename}'.");
+            }
+            VerifyStatus::Matches => {}
+            VerifyStatus::SizeMismatch { local, remote } => {
+                mismatched += 1;
+                let _ = writeln!(
+                    report,
+                    "Mismatch: '{filename}' is {local} bytes, but Google Fonts serves {remote} bytes."
                 );
             }
-        } else {
-            // Write the font file
-            if let Err(e) = fs::write(&font_output_path, font_file_bytes) {
-                return Err(format!(
-                    "Error writing font file '{}': {}",
-                    &font.get_font_filename(),
-                    e
-                )
-                .into());
-            } else if !quiet {
-                println!("Wrote font file to '{}'.", &font.get_font_filename());
+            VerifyStatus::Missing => {
+                missing += 1;
+                let _ = writeln!(report, "Missing: '{filename}'.");
+            }
+            VerifyStatus::UnknownSize => {
+                let _ = writeln!(
+                    report,
+                    "Unknown: Google Fonts didn't say how big '{filename}' is."
+                );
             }
         }
+    }
+    let _ = writeln!(
+        report,
+        "Verified {} font files: {mismatched} mismatched, {missing} missing.",
+        files.len()
+    );
+    report
+}
 
-        // Write the CSS file
-        let css_filename = font.get_css_filename();
-        let css_output_path = output_dir.join(&css_filename);
+/// Format fonts as a table with a column for each property, for `--list`.
+fn font_table(fonts: &[FontInfo]) -> String {
+    let header = [
+        "FAMILY", "STYLE", "WEIGHT", "STRETCH", "SUBSET", "FORMAT", "URL",
+    ]
+    .map(str::to_string);
+    let mut rows = vec![header];
+    for font in fonts {
+        rows.push([
+            font.family.clone(),
+            font.style.clone(),
+            font.weight.clone(),
+            font.stretch.clone().unwrap_or_default(),
+            font.writing_system_name.clone(),
+            font.format.to_css_format().to_string(),
+            font.url.clone(),
+        ]);
+    }
 
-        if css_output_path.exists() && !overwrite {
-            if !quiet {
-                println!(
-                    "Skipped writing to '{}' (file already exists, use --overwrite to overwrite).",
-                    css_output_path.display()
-                );
+    let mut widths = [0; 7];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let mut table = String::new();
+    for row in &rows {
+        let mut line = String::new();
+        for (cell, width) in row.iter().zip(widths) {
+            let _ = write!(line, "{cell:width$}  ");
+        }
+        let _ = writeln!(table, "{}", line.trim_end());
+    }
+    table
+}
+
+/// Read one URL per line, skipping empty lines and comments.
+fn read_url_list(reader: impl BufRead) -> io::Result<Vec<String>> {
+    let mut urls = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim();
+        if !line.is_empty() && !line.starts_with('#') {
+            urls.push(line.to_string());
+        }
+    }
+    Ok(urls)
+}
+
+/// The exit code for an error, so scripts can tell what kind of failure it was.
+///
+/// - 1: any other failure, like a font file on a host that isn't allowed, or files that failed `--verify`
+/// - 2: invalid command line arguments or config file, like a bad proxy URL or `--offline` without a cache directory
+/// - 3: a network failure, like a timeout or an error status
+/// - 4: CSS without any `@font-face` blocks
+/// - 5: a file or directory couldn't be read or written
+/// - 6: no fonts matched the requested subsets
+/// - 7: CSS with `@font-face` blocks that couldn't be parsed
+/// - 8: downloaded font files that weren't valid font files, like an error page
+fn exit_code(error: &DownloadError) -> i32 {
+    match error {
+        DownloadError::Network { .. }
+        | DownloadError::BadStatus { .. }
+        | DownloadError::TooLarge { .. } => 3,
+        DownloadError::HostNotAllowed { .. }
+        | DownloadError::UnsupportedScheme { .. }
+        | DownloadError::Client(_) => 1,
+        DownloadError::NoCacheDir | DownloadError::InvalidProxy { .. } => 2,
+        DownloadError::NoFonts { .. } => 4,
+        DownloadError::NotCached { .. }
+        | DownloadError::NotADirectory { .. }
+        | DownloadError::Io { .. }
+        | DownloadError::Manifest(_)
+        | DownloadError::ManifestToml(_) => 5,
+        DownloadError::NoMatchingSubsets { .. } => 6,
+        DownloadError::InvalidFonts { .. } => 7,
+        DownloadError::InvalidFontFiles { .. } => 8,
+        // Carrying on after failures only has one kind of failure if every URL failed the same way
+        DownloadError::UrlsFailed { failures, .. } => {
+            let mut codes = failures.iter().map(|failure| exit_code(&failure.error));
+            let first_code = codes.next().unwrap_or(1);
+            if codes.all(|code| code == first_code) {
+                first_code
+            } else {
+                1
             }
-        } else {
-            let css_content = font.get_new_css(fonts_prefix_in_css);
+        }
+    }
+}
 
-            if verbose {
-                println!("  Writing CSS file with updated font path: {css_filename}");
+/// Download the fonts and log a summary, or log the error and return its exit code.
+async fn download_and_summarize(config: &Config) -> Result<(), i32> {
+    match download(config).await {
+        Ok(fonts) => {
+            Event::Summary {
+                summary: &Summary::new(config.urls.len(), &fonts),
+                dry_run: config.dry_run,
             }
+            .log(config);
+            Ok(())
+        }
+        Err(e) => {
+            // Summarise what was downloaded before reporting what wasn't
+            let downloaded = match &e {
+                DownloadError::UrlsFailed { fonts, failures } => {
+                    Some((config.urls.len() - failures.len(), fonts))
+                }
+                DownloadError::InvalidFontFiles { fonts, .. } => Some((config.urls.len(), fonts)),
+                _ => None,
+            };
+            if let Some((css_urls, fonts)) = downloaded {
+                Event::Summary {
+                    summary: &Summary::new(css_urls, fonts),
+                    dry_run: config.dry_run,
+                }
+                .log(config);
+            }
+            Event::Error {
+                message: e.to_string(),
+            }
+            .log(config);
+            Err(exit_code(&e))
+        }
+    }
+}
+
+/// The files watched in watch mode, which are the config file, even if it doesn't exist yet, and the URL file.
+fn watched_files(matches: &ArgMatches) -> Vec<PathBuf> {
+    let config_file = matches
+        .get_one::<PathBuf>("config")
+        .cloned()
+        .unwrap_or_else(|| DEFAULT_CONFIG_FILE.into());
+    std::iter::once(config_file)
+        .chain(matches.get_one::<PathBuf>("url-file").cloned())
+        .collect()
+}
 
-            // Write the CSS file
-            if let Err(e) = fs::write(&css_output_path, css_content) {
-                return Err(format!("Error writing CSS file {css_filename}: {e}").into());
-            } else if !quiet {
-                println!("Wrote CSS file to '{css_filename}'.");
+/// When each file was last modified, or `None` if it doesn't exist.
+fn modified_times(paths: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    paths
+        .iter()
+        .map(|path| {
+            fs::metadata(path)
+                .and_then(|metadata| metadata.modified())
+                .ok()
+        })
+        .collect()
+}
+
+/// Download the fonts, and then again every time one of the watched files changes, until interrupted.
+/// The config file and URL file are read again each time, and errors are logged instead of exiting,
+/// so a mistake while editing one of them doesn't stop the watching.
+async fn watch(mut config: Config) -> ! {
+    let mut cli = build_cli();
+    let matches = cli.get_matches_mut();
+    let paths = watched_files(&matches);
+    let mut last_modified = modified_times(&paths);
+    let _ = download_and_summarize(&config).await;
+    loop {
+        Event::Watching { paths: &paths }.log(&config);
+        // Polling works the same everywhere, and checking twice a second is plenty for files edited by hand
+        let changed_path = loop {
+            tokio::time::sleep(WATCH_INTERVAL).await;
+            let modified = modified_times(&paths);
+            let changed = (0..paths.len()).find(|&i| modified[i] != last_modified[i]);
+            last_modified = modified;
+            if let Some(i) = changed {
+                break &paths[i];
+            }
+        };
+        Event::Changed { path: changed_path }.log(&config);
+        match config_from_args(&mut cli, &matches) {
+            Ok((new_config, _)) => {
+                config = new_config;
+                let _ = download_and_summarize(&config).await;
+            }
+            Err(e) => {
+                let _ = e.print();
             }
         }
     }
+}
 
-    Ok(())
+/// Log an error and exit with its exit code.
+fn exit_with_error(error: &DownloadError, config: &Config) -> ! {
+    Event::Error {
+        message: error.to_string(),
+    }
+    .log(config);
+    std::process::exit(exit_code(error));
 }
 
-fn main() {
-    let args = parse_args();
+#[tokio::main]
+async fn main() {
+    let (config, action) = parse_args();
 
-    // Create the output directory if it doesn't exist
-    if let Err(e) = ensure_output_dir(&args.output_dir) {
-        eprintln!("Failed to create output directory: '{e}'.");
-        std::process::exit(1);
+    if let Action::List = action {
+        match list(&config).await {
+            Ok(fonts) => print!("{}", font_table(&fonts)),
+            Err(e) => exit_with_error(&e, &config),
+        }
+        return;
     }
 
-    // Create a reusable HTTP client
-    let client = reqwest::blocking::Client::new();
+    if let Action::ListSubsets = action {
+        match list(&config).await {
+            Ok(fonts) => {
+                for subset in subset_names(&fonts) {
+                    println!("{subset}");
+                }
+            }
+            Err(e) => exit_with_error(&e, &config),
+        }
+        return;
+    }
 
-    // Download fonts from each URL
-    if let Err(e) = args.urls.iter().try_for_each(|url| {
-        download_fonts(
-            url,
-            &args.output_dir,
-            args.overwrite,
-            args.quiet,
-            args.verbose,
-            &args.fonts_prefix_in_css,
-            &client,
-        )
-    }) {
-        eprintln!("Error: {e}");
-        std::process::exit(1);
+    if let Action::PrintUrls = action {
+        match font_urls(&config).await {
+            Ok(urls) => {
+                for url in urls {
+                    println!("{url}");
+                }
+            }
+            Err(e) => exit_with_error(&e, &config),
+        }
+        return;
+    }
+
+    if let Action::PrintCss = action {
+        match rewritten_css(&config).await {
+            Ok(css) => print!("{css}"),
+            Err(e) => exit_with_error(&e, &config),
+        }
+        return;
+    }
+
+    if let Action::Verify = action {
+        match verify(&config).await {
+            Ok(files) => {
+                print!("{}", verify_report(&files, config.verbose));
+                if files.iter().any(|file| {
+                    matches!(
+                        file.status,
+                        VerifyStatus::SizeMismatch { .. } | VerifyStatus::Missing
+                    )
+                }) {
+                    std::process::exit(1);
+                }
+            }
+            Err(e) => exit_with_error(&e, &config),
+        }
+        return;
+    }
+
+    if let Action::Watch = action {
+        watch(config).await;
+    }
+
+    if let Err(code) = download_and_summarize(&config).await {
+        std::process::exit(code);
     }
 }
 
@@ -458,31 +1341,387 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_split_css_into_fonts() {
-        let css = r"/* latin */
+    fn test_subset_names() {
+        let fonts = google_fonts_downloader::split_css_into_fonts(
+            r"/* cyrillic-ext */
 @font-face {
-  font-family: 'Creepster';
+  font-family: 'Roboto';
   font-style: normal;
   font-weight: 400;
-  font-display: swap;
-  src: url(https://fonts.gstatic.com/s/creepster/v13/AlZy_zVUqJz4yMrniH4Rcn35fh4Dog.woff2) format('woff2');
-  unicode-range: U+0000-00FF, U+0131, U+0152-0153, U+02BB-02BC, U+02C6, U+02DA, U+02DC, U+0304, U+0308, U+0329, U+2000-206F, U+20AC, U+2122, U+2191, U+2193, U+2212, U+2215, U+FEFF, U+FFFD;
+  src: url(https://fonts.gstatic.com/s/roboto/v47/cyrillic-ext-400.woff2) format('woff2');
 }
 /* latin */
 @font-face {
-  font-family: 'Gravitas One';
+  font-family: 'Roboto';
+  font-style: normal;
+  font-weight: 400;
+  src: url(https://fonts.gstatic.com/s/roboto/v47/latin-400.woff2) format('woff2');
+}
+/* cyrillic-ext */
+@font-face {
+  font-family: 'Roboto';
+  font-style: normal;
+  font-weight: 700;
+  src: url(https://fonts.gstatic.com/s/roboto/v47/cyrillic-ext-700.woff2) format('woff2');
+}",
+        )
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+        assert_eq!(subset_names(&fonts), vec!["cyrillic-ext", "latin"]);
+    }
+
+    #[test]
+    fn test_watched_files() {
+        let matches = build_cli()
+            .try_get_matches_from([
+                "google-fonts-downloader",
+                "--watch",
+                "--url-file",
+                "urls.txt",
+            ])
+            .unwrap();
+        assert_eq!(
+            watched_files(&matches),
+            vec![
+                PathBuf::from(DEFAULT_CONFIG_FILE),
+                PathBuf::from("urls.txt")
+            ]
+        );
+
+        let matches = build_cli()
+            .try_get_matches_from([
+                "google-fonts-downloader",
+                "--watch",
+                "--config",
+                "fonts.toml",
+            ])
+            .unwrap();
+        assert_eq!(watched_files(&matches), vec![PathBuf::from("fonts.toml")]);
+    }
+
+    #[test]
+    fn test_output_to_stdout() {
+        let parse = |args: &[&str]| {
+            let mut cli = build_cli();
+            let matches = cli
+                .clone()
+                .try_get_matches_from(
+                    [
+                        "google-fonts-downloader",
+                        "-o",
+                        "-",
+                        "https://example.com/css",
+                    ]
+                    .iter()
+                    .chain(args),
+                )
+                .unwrap();
+            config_from_args(&mut cli, &matches)
+        };
+
+        let (config, action) = parse(&["--css-only"]).unwrap();
+        assert!(config.output_is_stdout());
+        assert!(matches!(action, Action::PrintCss));
+        // Info goes to stderr, so it doesn't need to be silenced
+        assert!(!config.quiet);
+
+        let error = parse(&[]).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::ArgumentConflict);
+        let error = parse(&["--css-only", "--watch"]).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn test_quiet_conflicts_with_verbose() {
+        let error = build_cli()
+            .try_get_matches_from([
+                "google-fonts-downloader",
+                "-q",
+                "-v",
+                "https://example.com/css",
+            ])
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn test_exit_code() {
+        let bad_status = || DownloadError::BadStatus {
+            url: "https://fonts.googleapis.com/css2?family=Missing".to_string(),
+            status: 404,
+        };
+        let no_fonts = || DownloadError::NoFonts {
+            url: "https://example.com/".to_string(),
+        };
+        assert_eq!(exit_code(&bad_status()), 3);
+        assert_eq!(exit_code(&no_fonts()), 4);
+        assert_eq!(
+            exit_code(&DownloadError::Io {
+                message: "Error writing CSS file 'fonts.css'".to_string(),
+                source: io::Error::other("disk full"),
+            }),
+            5
+        );
+        assert_eq!(exit_code(&DownloadError::NoCacheDir), 2);
+        assert_eq!(
+            exit_code(&DownloadError::InvalidProxy {
+                url: "localhost:8080".to_string(),
+                reason: "relative URL without a base".to_string(),
+            }),
+            2
+        );
+        assert_eq!(
+            exit_code(&DownloadError::InvalidFonts {
+                url: "https://example.com/".to_string(),
+                blocks: 2,
+            }),
+            7
+        );
+        assert_eq!(
+            exit_code(&DownloadError::InvalidFontFiles {
+                fonts: Vec::new(),
+                urls: vec!["https://fonts.gstatic.com/s/roboto/v1/latin.woff2".to_string()],
+            }),
+            8
+        );
+
+        let urls_failed = |errors: Vec<DownloadError>| DownloadError::UrlsFailed {
+            fonts: Vec::new(),
+            failures: errors
+                .into_iter()
+                .map(|error| google_fonts_downloader::UrlFailure {
+                    url: "https://fonts.googleapis.com/css2?family=Missing".to_string(),
+                    error,
+                })
+                .collect(),
+        };
+        assert_eq!(exit_code(&urls_failed(vec![bad_status(), bad_status()])), 3);
+        assert_eq!(exit_code(&urls_failed(vec![bad_status(), no_fonts()])), 1);
+    }
+
+    #[test]
+    fn test_read_url_list() {
+        let url_list = "# Fonts for the website
+https://fonts.googleapis.com/css2?family=Roboto&display=swap
+
+  https://fonts.googleapis.com/css2?family=Creepster&display=swap
+";
+        let urls = read_url_list(url_list.as_bytes()).unwrap();
+        assert_eq!(
+            urls,
+            vec![
+                "https://fonts.googleapis.com/css2?family=Roboto&display=swap",
+                "https://fonts.googleapis.com/css2?family=Creepster&display=swap",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_font_table() {
+        let fonts = google_fonts_downloader::split_css_into_fonts(
+            r"/* latin */
+@font-face {
+  font-family: 'Roboto';
+  font-style: italic;
+  font-weight: 700;
+  src: url(https://fonts.gstatic.com/s/roboto/v47/latin-700-italic.woff2) format('woff2');
+}
+/* cyrillic-ext */
+@font-face {
+  font-family: 'Roboto Flex';
   font-style: normal;
   font-weight: 400;
-  font-display: swap;
-  src: url(https://fonts.gstatic.com/s/gravitasone/v19/5h1diZ4hJ3cblKy3LWakKQmqCm5MjXPjbA.woff2) format('woff2');
-  unicode-range: U+0000-00FF, U+0131, U+0152-0153, U+02BB-02BC, U+02C6, U+02DA, U+02DC, U+0304, U+0308, U+0329, U+2000-206F, U+20AC, U+2122, U+2191, U+2193, U+2212, U+2215, U+FEFF, U+FFFD;
-}";
-
-        let result = split_css_into_fonts(css);
-        assert_eq!(result.len(), 2);
-        assert_eq!(result[0].writing_system_name, "latin");
-        assert_eq!(result[0].css, "@font-face {\n  font-family: 'Creepster';\n  font-style: normal;\n  font-weight: 400;\n  font-display: swap;\n  src: url(https://fonts.gstatic.com/s/creepster/v13/AlZy_zVUqJz4yMrniH4Rcn35fh4Dog.woff2) format('woff2');\n  unicode-range: U+0000-00FF, U+0131, U+0152-0153, U+02BB-02BC, U+02C6, U+02DA, U+02DC, U+0304, U+0308, U+0329, U+2000-206F, U+20AC, U+2122, U+2191, U+2193, U+2212, U+2215, U+FEFF, U+FFFD;\n}");
-        assert_eq!(result[1].writing_system_name, "latin");
-        assert_eq!(result[1].css, "@font-face {\n  font-family: 'Gravitas One';\n  font-style: normal;\n  font-weight: 400;\n  font-display: swap;\n  src: url(https://fonts.gstatic.com/s/gravitasone/v19/5h1diZ4hJ3cblKy3LWakKQmqCm5MjXPjbA.woff2) format('woff2');\n  unicode-range: U+0000-00FF, U+0131, U+0152-0153, U+02BB-02BC, U+02C6, U+02DA, U+02DC, U+0304, U+0308, U+0329, U+2000-206F, U+20AC, U+2122, U+2191, U+2193, U+2212, U+2215, U+FEFF, U+FFFD;\n}");
+  font-stretch: 75%;
+  src: url(https://fonts.gstatic.com/s/robotoflex/v26/cyrillic-ext.woff2) format('woff2');
+}",
+        )
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+        assert_eq!(
+            font_table(&fonts),
+            "FAMILY       STYLE   WEIGHT  STRETCH  SUBSET        FORMAT  URL
+Roboto       italic  700              latin         woff2   https://fonts.gstatic.com/s/roboto/v47/latin-700-italic.woff2
+Roboto Flex  normal  400     75%      cyrillic-ext  woff2   https://fonts.gstatic.com/s/robotoflex/v26/cyrillic-ext.woff2
+"
+        );
+    }
+
+    #[test]
+    fn test_verify_report() {
+        let file = |filename: &str, status| VerifiedFile {
+            url: String::new(),
+            filename: filename.to_string(),
+            status,
+        };
+        let files = [
+            file("roboto-400-normal-latin.woff2", VerifyStatus::Matches),
+            file(
+                "roboto-700-normal-latin.woff2",
+                VerifyStatus::SizeMismatch {
+                    local: 100,
+                    remote: 120,
+                },
+            ),
+            file("roboto-400-italic-latin.woff2", VerifyStatus::Missing),
+        ];
+        assert_eq!(
+            verify_report(&files, false),
+            "Mismatch: 'roboto-700-normal-latin.woff2' is 100 bytes, but Google Fonts serves 120 bytes.
+Missing: 'roboto-400-italic-latin.woff2'.
+Verified 3 font files: 1 mismatched, 1 missing.
+"
+        );
+        assert!(
+            verify_report(&files, true).starts_with("Matches: 'roboto-400-normal-latin.woff2'.\n")
+        );
+    }
+
+    #[test]
+    fn test_expand_path() {
+        let var = |name: &str| match name {
+            "HOME" => Some("/home/user".to_string()),
+            "FONTS_DIR" => Some("static/fonts".to_string()),
+            _ => None,
+        };
+        assert_eq!(
+            expand_path_with("~", var).unwrap(),
+            PathBuf::from("/home/user")
+        );
+        assert_eq!(
+            expand_path_with("~/fonts", var).unwrap(),
+            PathBuf::from("/home/user/fonts")
+        );
+        assert_eq!(
+            expand_path_with("~user/fonts", var).unwrap(),
+            PathBuf::from("~user/fonts")
+        );
+        assert_eq!(
+            expand_path_with("./fonts/~", var).unwrap(),
+            PathBuf::from("./fonts/~")
+        );
+        assert_eq!(
+            expand_path_with("~/$FONTS_DIR/${FONTS_DIR}/%FONTS_DIR%", var).unwrap(),
+            PathBuf::from("/home/user/static/fonts/static/fonts/static/fonts")
+        );
+        assert_eq!(
+            expand_path_with("fonts-$/100%", var).unwrap(),
+            PathBuf::from("fonts-$/100%")
+        );
+        assert!(expand_path_with("$MISSING/fonts", var).is_err());
+        assert!(expand_path_with("~/fonts", |_| None).is_err());
+    }
+
+    #[test]
+    fn test_parse_font_spec() {
+        assert_eq!(
+            parse_font_spec("Roboto").unwrap(),
+            "https://fonts.googleapis.com/css2?family=Roboto"
+        );
+        assert_eq!(
+            parse_font_spec("Roboto:wght@400;700").unwrap(),
+            "https://fonts.googleapis.com/css2?family=Roboto:wght@400;700"
+        );
+        assert_eq!(
+            parse_font_spec("Open Sans:ital,wght@0,400;1,700").unwrap(),
+            "https://fonts.googleapis.com/css2?family=Open+Sans:ital,wght@0,400;1,700"
+        );
+        assert_eq!(
+            parse_font_spec("M PLUS 1p&Co").unwrap(),
+            "https://fonts.googleapis.com/css2?family=M+PLUS+1p%26Co"
+        );
+        assert!(parse_font_spec("").is_err());
+        assert!(parse_font_spec(":wght@400").is_err());
+        assert!(parse_font_spec("Roboto:").is_err());
+        assert!(parse_font_spec("Roboto:wght@400&text=abc").is_err());
+    }
+
+    #[test]
+    fn test_font_display_parameter() {
+        let parse = |args: &[&str]| {
+            let mut cli = build_cli();
+            let matches = cli
+                .clone()
+                .try_get_matches_from(
+                    ["google-fonts-downloader", "--font", "Roboto"]
+                        .iter()
+                        .chain(args),
+                )
+                .unwrap();
+            config_from_args(&mut cli, &matches).unwrap().0
+        };
+
+        // Without --display, Google Fonts' own default is left alone
+        assert_eq!(
+            parse(&[]).urls,
+            vec!["https://fonts.googleapis.com/css2?family=Roboto"]
+        );
+        // The text is added when the CSS is requested, after the display parameter
+        let config = parse(&["--display", "optional", "--text", "Hello"]);
+        assert_eq!(
+            config.urls,
+            vec!["https://fonts.googleapis.com/css2?family=Roboto&display=optional"]
+        );
+        assert_eq!(config.text.as_deref(), Some("Hello"));
+        // Only URLs built from --font get the parameter
+        let config = parse(&["--display", "block", "https://example.com/fonts.css"]);
+        assert_eq!(
+            config.urls,
+            vec![
+                "https://example.com/fonts.css",
+                "https://fonts.googleapis.com/css2?family=Roboto&display=block"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_rate_limit() {
+        assert!(parse_rate_limit("5").is_ok_and(|rate| (rate - 5.0).abs() < f64::EPSILON));
+        assert!(parse_rate_limit("0.5").is_ok_and(|rate| (rate - 0.5).abs() < f64::EPSILON));
+        assert!(parse_rate_limit("0").is_err());
+        assert!(parse_rate_limit("-1").is_err());
+        assert!(parse_rate_limit("inf").is_err());
+        assert!(parse_rate_limit("fast").is_err());
+    }
+
+    #[test]
+    fn test_parse_base_url() {
+        assert_eq!(
+            parse_base_url("https://cdn.example.com/fonts/").unwrap(),
+            "https://cdn.example.com/fonts"
+        );
+        assert_eq!(
+            parse_base_url("http://localhost:8080").unwrap(),
+            "http://localhost:8080"
+        );
+        assert!(parse_base_url("/fonts").is_err());
+        assert!(parse_base_url("ftp://cdn.example.com/fonts").is_err());
+        assert!(parse_base_url("https://cdn.example.com/fonts?v=1").is_err());
+    }
+
+    #[test]
+    fn test_config_file() {
+        let config_file: ConfigFile = toml::from_str(
+            r#"
+output = "static/fonts"
+fonts-prefix = "/fonts"
+format = "woff"
+subsets = ["latin", "latin-ext"]
+urls = ["https://fonts.googleapis.com/css2?family=Roboto&display=swap"]
+"#,
+        )
+        .unwrap();
+        assert_eq!(config_file.output, Some(PathBuf::from("static/fonts")));
+        assert_eq!(config_file.fonts_prefix.as_deref(), Some("/fonts"));
+        assert_eq!(config_file.format.as_deref(), Some("woff"));
+        assert_eq!(config_file.subsets, vec!["latin", "latin-ext"]);
+        assert_eq!(
+            config_file.urls,
+            vec!["https://fonts.googleapis.com/css2?family=Roboto&display=swap"]
+        );
+
+        assert!(toml::from_str::<ConfigFile>("output-dir = \"fonts\"").is_err());
     }
 }
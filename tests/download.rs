@@ -0,0 +1,296 @@
+//! Run the real download path, with the real HTTP client, against a local server serving canned responses.
+
+use google_fonts_downloader::{Config, DownloadError, download};
+use std::fmt::Write;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use wiremock::matchers::{method, path, query_param};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+/// The start of a WOFF2 file, which is enough for it to be recognised as one.
+const WOFF2_BYTES: &[u8] = b"wOF2\x00\x01\x00\x00font data";
+
+/// A `css2` response for Roboto with a font file for two writing systems, served by the mock server.
+fn roboto_css(server: &MockServer) -> String {
+    let mut css = String::new();
+    for subset in ["latin", "latin-ext"] {
+        write!(
+            css,
+            "/* {subset} */\n@font-face {{\n  font-family: 'Roboto';\n  font-style: normal;\n  font-weight: 400;\n  src: url({}/s/roboto/v1/{subset}.woff2) format('woff2');\n}}\n",
+            server.uri()
+        )
+        .unwrap();
+    }
+    css
+}
+
+/// Serve the Roboto CSS and both of its font files.
+async fn serve_roboto(server: &MockServer) {
+    Mock::given(method("GET"))
+        .and(path("/css2"))
+        .and(query_param("family", "Roboto"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(roboto_css(server), "text/css"))
+        .mount(server)
+        .await;
+    for subset in ["latin", "latin-ext"] {
+        Mock::given(method("GET"))
+            .and(path(format!("/s/roboto/v1/{subset}.woff2")))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(WOFF2_BYTES, "font/woff2"))
+            .mount(server)
+            .await;
+    }
+}
+
+/// An empty temporary directory for a test, which is removed when it's dropped, even if the test fails.
+struct TestDir(PathBuf);
+
+impl TestDir {
+    fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn test_dir(name: &str) -> TestDir {
+    let path = std::env::temp_dir().join(format!(
+        "google-fonts-downloader-integration-{name}-{}",
+        std::process::id()
+    ));
+    let _ = fs::remove_dir_all(&path);
+    fs::create_dir_all(&path).unwrap();
+    TestDir(path)
+}
+
+/// A config downloading from the mock server into a `fonts` directory, which doesn't exist yet, in a test directory.
+fn test_config(dir: &TestDir, server: &MockServer, urls: &[&str]) -> Config {
+    Config {
+        urls: urls
+            .iter()
+            .map(|url| format!("{}{url}", server.uri()))
+            .collect(),
+        output_dir: dir.path().join("fonts"),
+        allowed_hosts: vec!["127.0.0.1".to_string()],
+        quiet: true,
+        ..Config::default()
+    }
+}
+
+/// The names of the files in a directory, sorted.
+fn file_names(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    names
+}
+
+#[tokio::test]
+async fn test_download_writes_fonts_and_css() {
+    let server = MockServer::start().await;
+    serve_roboto(&server).await;
+    let dir = test_dir("writes");
+    let config = test_config(&dir, &server, &["/css2?family=Roboto"]);
+
+    let fonts = download(&config).await.unwrap();
+    assert_eq!(fonts.len(), 2);
+    assert_eq!(
+        file_names(&config.output_dir),
+        vec![
+            "roboto-400-normal-latin-ext.css",
+            "roboto-400-normal-latin-ext.woff2",
+            "roboto-400-normal-latin.css",
+            "roboto-400-normal-latin.woff2",
+        ]
+    );
+    assert_eq!(
+        fs::read(config.output_dir.join("roboto-400-normal-latin.woff2")).unwrap(),
+        WOFF2_BYTES
+    );
+    assert_eq!(
+        fs::read_to_string(config.output_dir.join("roboto-400-normal-latin.css")).unwrap(),
+        "/* latin */\n@font-face {\n  font-family: 'Roboto';\n  font-style: normal;\n  font-weight: 400;\n  src: url(./roboto-400-normal-latin.woff2) format('woff2');\n}"
+    );
+}
+
+#[tokio::test]
+async fn test_download_skips_error_pages() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/css2"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(roboto_css(&server), "text/css"))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/s/roboto/v1/latin.woff2"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(WOFF2_BYTES, "font/woff2"))
+        .mount(&server)
+        .await;
+    // A server that answers with a page instead of a font
+    Mock::given(method("GET"))
+        .and(path("/s/roboto/v1/latin-ext.woff2"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw("<!DOCTYPE html>", "text/html"))
+        .mount(&server)
+        .await;
+    let dir = test_dir("error-pages");
+    let config = test_config(&dir, &server, &["/css2?family=Roboto"]);
+
+    // The other font is still written, but the run fails so scripts notice
+    let Err(DownloadError::InvalidFontFiles { fonts, urls }) = download(&config).await else {
+        panic!("expected the error page to fail the download");
+    };
+    assert_eq!(fonts.len(), 1);
+    assert_eq!(
+        urls,
+        vec![format!("{}/s/roboto/v1/latin-ext.woff2", server.uri())]
+    );
+    assert_eq!(
+        file_names(&config.output_dir),
+        vec![
+            "roboto-400-normal-latin.css",
+            "roboto-400-normal-latin.woff2"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_download_missing_css() {
+    let server = MockServer::start().await;
+    serve_roboto(&server).await;
+    let dir = test_dir("missing");
+    let config = test_config(
+        &dir,
+        &server,
+        &["/css2?family=Missing", "/css2?family=Roboto"],
+    );
+
+    // Without carrying on, a missing stylesheet stops everything
+    let result = download(&config).await;
+    assert!(matches!(
+        result,
+        Err(DownloadError::BadStatus { status: 404, .. })
+    ));
+    assert!(file_names(&config.output_dir).is_empty());
+
+    // Carrying on downloads the fonts from the other URL, and still reports the one that failed
+    let config = Config {
+        keep_going: true,
+        ..config
+    };
+    let Err(DownloadError::UrlsFailed { fonts, failures }) = download(&config).await else {
+        panic!("expected some URLs to fail");
+    };
+    assert_eq!(fonts.len(), 2);
+    assert_eq!(failures.len(), 1);
+    assert_eq!(
+        failures[0].url,
+        format!("{}/css2?family=Missing", server.uri())
+    );
+    assert_eq!(file_names(&config.output_dir).len(), 4);
+}
+
+#[tokio::test]
+async fn test_download_too_large_font() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/css2"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(roboto_css(&server), "text/css"))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/s/roboto/v1/latin.woff2"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(WOFF2_BYTES, "font/woff2"))
+        .mount(&server)
+        .await;
+    let mut large_font = WOFF2_BYTES.to_vec();
+    large_font.resize(5000, 0);
+    Mock::given(method("GET"))
+        .and(path("/s/roboto/v1/latin-ext.woff2"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(large_font, "font/woff2"))
+        .mount(&server)
+        .await;
+    let dir = test_dir("too-large");
+    let config = Config {
+        max_bytes: Some(2000),
+        ..test_config(&dir, &server, &["/css2?family=Roboto"])
+    };
+
+    let Err(error) = download(&config).await else {
+        panic!("expected the large font to fail");
+    };
+    let DownloadError::TooLarge {
+        url,
+        size,
+        max_bytes,
+    } = &error
+    else {
+        panic!("expected a too large error, got {error}");
+    };
+    assert_eq!(
+        url,
+        &format!("{}/s/roboto/v1/latin-ext.woff2", server.uri())
+    );
+    assert_eq!((*size, *max_bytes), (5000, 2000));
+    assert!(error.to_string().contains("at least 5000 bytes"));
+    // The limit stops the whole download, so the smaller font file isn't written either
+    assert!(
+        !config
+            .output_dir
+            .join("roboto-400-normal-latin.woff2")
+            .exists()
+    );
+}
+
+#[tokio::test]
+async fn test_download_into_zip() {
+    let server = MockServer::start().await;
+    serve_roboto(&server).await;
+    let dir = test_dir("zip");
+    let config = test_config(&dir, &server, &["/css2?family=Roboto"]);
+    let zip_path = dir.path().join("fonts.zip");
+    let config = Config {
+        zip: Some(zip_path.clone()),
+        combine_css: Some("fonts.css".to_string()),
+        ..config
+    };
+
+    let fonts = download(&config).await.unwrap();
+    assert_eq!(fonts.len(), 2);
+    // Nothing is written to the output directory
+    assert!(!config.output_dir.exists());
+
+    let mut archive = zip::ZipArchive::new(fs::File::open(&zip_path).unwrap()).unwrap();
+    let mut names: Vec<String> = archive
+        .file_names()
+        .map(|name| name.unwrap().into_owned())
+        .collect();
+    names.sort_unstable();
+    assert_eq!(
+        names,
+        vec![
+            "fonts.css",
+            "roboto-400-normal-latin-ext.woff2",
+            "roboto-400-normal-latin.woff2",
+        ]
+    );
+    let mut font_file = Vec::new();
+    archive
+        .by_name("roboto-400-normal-latin.woff2")
+        .unwrap()
+        .read_to_end(&mut font_file)
+        .unwrap();
+    assert_eq!(font_file, WOFF2_BYTES);
+    let mut css = String::new();
+    archive
+        .by_name("fonts.css")
+        .unwrap()
+        .read_to_string(&mut css)
+        .unwrap();
+    assert!(css.contains("src: url(./roboto-400-normal-latin.woff2) format('woff2');"));
+}